@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+use serde::Deserialize;
+use toml::Spanned;
+use crate::{Bonus, Bonuses, Color, ItemSlot, Profession};
+use crate::procs::OnUse;
+use crate::items::{Consumable, ConsumableCategory, Enchantment, Gem, GemLimit, MetaActivation};
+
+const DEFAULT_CATALOG: &str = include_str!("data/catalog.toml");
+
+#[derive(Debug)]
+pub enum CatalogError {
+    Io { path: String, source: std::io::Error },
+    Parse { line: usize, message: String },
+    Invalid { entry: String, line: usize, reason: String },
+}
+
+impl std::fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CatalogError::Io { path, source } => write!(f, "can't read catalog {}: {}", path, source),
+            CatalogError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            CatalogError::Invalid { entry, line, reason } => write!(f, "line {}: entry \"{}\" {}", line, entry, reason),
+        }
+    }
+}
+
+impl std::error::Error for CatalogError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GemEntry {
+    name: String,
+    colors: Vec<Color>,
+    bonuses: Vec<Bonus>,
+    #[serde(default)]
+    activation: Vec<MetaActivation>,
+    #[serde(default)]
+    limit: Option<GemLimit>,
+    #[serde(default)]
+    profession: Option<Profession>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnchantEntry {
+    name: String,
+    slots: Vec<ItemSlot>,
+    #[serde(default)]
+    bonuses: Vec<Bonus>,
+    #[serde(default)]
+    on_use: Vec<OnUse>,
+    #[serde(default)]
+    profession: Option<Profession>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FoodEntry {
+    name: String,
+    bonuses: Vec<Bonus>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConsumableEntry {
+    name: String,
+    category: ConsumableCategory,
+    bonuses: Vec<Bonus>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CatalogFile {
+    #[serde(default)]
+    gem: Vec<Spanned<GemEntry>>,
+    #[serde(default)]
+    enchant: Vec<Spanned<EnchantEntry>>,
+    #[serde(default)]
+    food: Vec<Spanned<FoodEntry>>,
+    #[serde(default)]
+    consumable: Vec<Spanned<ConsumableEntry>>,
+}
+
+#[derive(Clone)]
+pub struct Catalog {
+    gems: Vec<Gem>,
+    enchants: Vec<Enchantment>,
+    consumables: Vec<Consumable>,
+}
+
+impl Catalog {
+    pub fn new() -> Self {
+        return Self{gems: Vec::new(), enchants: Vec::new(), consumables: Vec::new()};
+    }
+    pub fn default_catalog() -> Self {
+        return Self::from_str(DEFAULT_CATALOG).expect("Built-in catalog is malformed!");
+    }
+    pub fn from_file(path: &Path) -> Result<Self, CatalogError> {
+        let src = std::fs::read_to_string(path).map_err(|source| CatalogError::Io{path: path.display().to_string(), source})?;
+        return Self::from_str(&src);
+    }
+    pub fn merge(&mut self, other: Catalog) -> () {
+        self.gems.extend(other.gems);
+        self.enchants.extend(other.enchants);
+        self.consumables.extend(other.consumables);
+    }
+    pub fn retain_gems(&mut self, keep: impl FnMut(&Gem) -> bool) -> () {
+        self.gems.retain(keep);
+    }
+    pub fn retain_enchants(&mut self, keep: impl FnMut(&Enchantment) -> bool) -> () {
+        self.enchants.retain(keep);
+    }
+    pub fn get_gems(&self) -> &[Gem] { return &self.gems; }
+    pub fn get_enchants(&self) -> &[Enchantment] { return &self.enchants; }
+    pub fn get_consumables(&self) -> &[Consumable] { return &self.consumables; }
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl FromStr for Catalog {
+    type Err = CatalogError;
+    fn from_str(src: &str) -> Result<Self, CatalogError> {
+        let file: CatalogFile = toml::from_str(src).map_err(|err| CatalogError::Parse{
+            line: err.span().map_or(0, |span| line_of(src, span.start)),
+            message: err.message().to_owned(),
+        })?;
+        let mut res = Self::new();
+        let mut seen = HashSet::new();
+        for entry in &file.gem {
+            let line = line_of(src, entry.span().start);
+            let gem = entry.get_ref();
+            check_entry(&gem.name, line, !gem.bonuses.is_empty(), &mut seen, "gem")?;
+            if gem.colors.is_empty() { return Err(invalid(&gem.name, line, "has no colors")); }
+            if gem.colors.contains(&Color::Prismatic) { return Err(invalid(&gem.name, line, "is colored Prismatic, which only sockets can be")); }
+            if let Some(GemLimit::Category{ref category, count}) = gem.limit {
+                if category.trim().is_empty() || count == 0 { return Err(invalid(&gem.name, line, "has a category limit without a category or allowing no gems")); }
+            }
+            let mut res_gem = if gem.colors.contains(&Color::Meta) {
+                if gem.colors.len() > 1 { return Err(invalid(&gem.name, line, "is a meta gem with other colors")); }
+                Gem::new_meta(Bonuses::new(gem.bonuses.clone()), gem.name.clone(), gem.activation.clone())
+            } else {
+                if !gem.activation.is_empty() { return Err(invalid(&gem.name, line, "has an activation rule but isn't a meta gem")); }
+                Gem::new(gem.colors.clone(), Bonuses::new(gem.bonuses.clone()), gem.name.clone())
+            };
+            res_gem.set_limit(gem.limit.clone());
+            res_gem.set_profession(gem.profession);
+            res.gems.push(res_gem);
+        }
+        seen.clear();
+        for entry in &file.enchant {
+            let line = line_of(src, entry.span().start);
+            let enchant = entry.get_ref();
+            check_entry(&enchant.name, line, !enchant.bonuses.is_empty() || !enchant.on_use.is_empty(), &mut seen, "enchant")?;
+            if enchant.slots.is_empty() { return Err(invalid(&enchant.name, line, "has no slots")); }
+            if let Some(slot) = enchant.slots.iter().find(|slot| !slot.can_enchant(enchant.profession)) {
+                let reason = match enchant.profession {
+                    Some(profession) => format!("can't go on {} for {}", slot, profession),
+                    None => format!("can't go on {} without a profession", slot),
+                };
+                return Err(invalid(&enchant.name, line, &reason));
+            }
+            for slot in &enchant.slots {
+                let mut res_enchant = Enchantment::new(*slot, Bonuses::new(enchant.bonuses.clone()), enchant.name.clone());
+                res_enchant.set_on_use(enchant.on_use.clone());
+                res_enchant.set_profession(enchant.profession);
+                res.enchants.push(res_enchant);
+            }
+        }
+        seen.clear();
+        for entry in &file.food {
+            let line = line_of(src, entry.span().start);
+            let food = entry.get_ref();
+            check_entry(&food.name, line, !food.bonuses.is_empty(), &mut seen, "food")?;
+            res.consumables.push(Consumable::new(food.name.clone(), ConsumableCategory::Food, Bonuses::new(food.bonuses.clone())));
+        }
+        // Food entries are consumables of the Food category, so they share names with the others.
+        for entry in &file.consumable {
+            let line = line_of(src, entry.span().start);
+            let consumable = entry.get_ref();
+            check_entry(&consumable.name, line, !consumable.bonuses.is_empty(), &mut seen, "consumable")?;
+            res.consumables.push(Consumable::new(consumable.name.clone(), consumable.category, Bonuses::new(consumable.bonuses.clone())));
+        }
+        return Ok(res);
+    }
+}
+
+fn line_of(src: &str, offset: usize) -> usize {
+    return src[..offset.min(src.len())].matches('\n').count() + 1;
+}
+
+fn invalid(entry: &str, line: usize, reason: &str) -> CatalogError {
+    return CatalogError::Invalid{entry: entry.to_owned(), line, reason: reason.to_owned()};
+}
+
+fn check_entry(name: &str, line: usize, has_bonuses: bool, seen: &mut HashSet<String>, kind: &str) -> Result<(), CatalogError> {
+    if name.trim().is_empty() { return Err(invalid(name, line, &format!("is a {} without a name", kind))); }
+    if !seen.insert(name.to_owned()) { return Err(invalid(name, line, &format!("is a duplicate {}", kind))); }
+    if !has_bonuses { return Err(invalid(name, line, "has no bonuses")); }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_catalog_loads() {
+        let catalog = Catalog::default_catalog();
+        assert_eq!(catalog.get_gems().len(), 33);
+        assert_eq!(catalog.get_gems().iter().filter(|gem| gem.is_meta()).count(), 4);
+        assert_eq!(catalog.get_consumables().iter().filter(|consumable| consumable.get_category() == ConsumableCategory::Food).count(), 2);
+        assert_eq!(catalog.get_consumables().iter().filter(|consumable| consumable.get_category() == ConsumableCategory::Flask).count(), 2);
+        assert_eq!(catalog.get_enchants().iter().filter(|enchant| enchant.get_slot() == ItemSlot::Gloves).count(), 7);
+    }
+    #[test]
+    fn invalid_entry_named() {
+        let src = "[[gem]]\nname = \"ok\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"Agility\", val = 20 }]\n\n[[gem]]\nname = \"broken\"\ncolors = []\nbonuses = [{ stat = \"Agility\", val = 20 }]\n";
+        match Catalog::from_str(src) {
+            Err(CatalogError::Invalid{entry, line, ..}) => assert!(entry == "broken" && line == 6),
+            _ => panic!("Empty colors must be rejected!"),
+        }
+    }
+    #[test]
+    fn ring_enchants_need_enchanting() {
+        let src = "[[enchant]]\nname = \"assault\"\nslots = [\"Ring1\", \"Ring2\"]\nbonuses = [{ stat = \"AttackPower\", val = 40 }]\n";
+        match Catalog::from_str(src) {
+            Err(CatalogError::Invalid{entry, reason, ..}) => assert!(entry == "assault" && reason.contains("Ring1")),
+            _ => panic!("Ring enchants without Enchanting must be rejected!"),
+        }
+        assert!(Catalog::from_str(&format!("{}profession = \"Enchanting\"\n", src)).is_ok());
+    }
+    #[test]
+    fn parse_error_line() {
+        let src = "[[food]]\nname = \"loin\"\nbonuses = [{ stat = \"Luck\", val = 20 }]\n";
+        match Catalog::from_str(src) {
+            Err(CatalogError::Parse{line, ..}) => assert_eq!(line, 3),
+            _ => panic!("Unknown stat must be rejected!"),
+        }
+    }
+}
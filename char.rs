@@ -1,10 +1,14 @@
 use std::collections::HashMap;
 use crate::{ItemSlot, Stat, ITEM_SLOTS_ORDER};
-use crate::items::Item;
-use crate::simulator::Requirement;
+use crate::items::{Item, ItemSet};
+use crate::ratings::RatingTable;
+use crate::simulator::{GainFunction, Requirement};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 
 
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "Vec<Item>", into = "Vec<Item>")]
 pub struct ItemBuild {
     build: HashMap<ItemSlot, Option<Item>>,
 } 
@@ -23,9 +27,43 @@ impl ItemBuild {
     pub fn get_item(&self, slot: ItemSlot) -> &Option<Item> {
         if self.build.contains_key(&slot) { return self.build.get(&slot).unwrap(); } else { return &None }
     }
+    pub fn get_item_mut(&mut self, slot: ItemSlot) -> Option<&mut Item> {
+        return self.build.get_mut(&slot).and_then(|opt_item| opt_item.as_mut());
+    }
     pub fn clear(&mut self) -> () {
         self.build.clear();
     }
+    pub fn count_set_pieces(&self, set: &ItemSet, slots: &[ItemSlot]) -> u32 {
+        return self.item_iter().filter(|(slot, opt_item)| slots.contains(slot) && opt_item.as_ref().is_some_and(|item| item.get_item_set() == Some(set.get_name()))).count() as u32;
+    }
+    // Pieces in locked and rotated slots count together and every threshold applies once. The current stats are taken to be the
+    // character's with only the locked items on, so they hold the bonuses those reach on their own already, just like the stats of
+    // the items themselves, and only the thresholds that need the rotated pieces are added here.
+    pub fn apply_set_bonuses(&self, sets: &[ItemSet], rotated_slots: &[ItemSlot], cur_stats: &mut CurStats) -> () {
+        let locked_slots: Vec<ItemSlot> = ITEM_SLOTS_ORDER.iter().filter(|slot| !rotated_slots.contains(slot)).copied().collect();
+        for set in sets {
+            let (pieces, locked_pieces) = (self.count_set_pieces(set, &ITEM_SLOTS_ORDER), self.count_set_pieces(set, &locked_slots));
+            for bonus in set.get_bonuses() {
+                if pieces >= bonus.get_pieces() && locked_pieces < bonus.get_pieces() { bonus.get_bonuses().apply_bonuses(cur_stats); }
+            }
+        }
+    }
+}
+
+impl From<Vec<Item>> for ItemBuild {
+    fn from(items: Vec<Item>) -> Self {
+        let mut res = Self::new();
+        for item in items {
+            res.lock_item(item);
+        }
+        return res;
+    }
+}
+
+impl From<ItemBuild> for Vec<Item> {
+    fn from(build: ItemBuild) -> Self {
+        return build.item_iter().filter_map(|(_, opt_item)| opt_item.clone()).collect();
+    }
 }
 
 pub struct ItemBuildIter<'a> {
@@ -43,6 +81,8 @@ impl<'a> Iterator for ItemBuildIter<'a> {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "Vec<Item>", into = "Vec<Item>")]
 pub struct Rotatables {
     rotatable_items: HashMap<ItemSlot, Vec<Item>>,
     which_slots_to_rotate: Vec<ItemSlot>,
@@ -62,11 +102,34 @@ impl Rotatables {
     pub fn get_from_slot(&self, slot: ItemSlot) -> &[Item] {
         return self.rotatable_items.get(&slot).unwrap();
     }
+    pub fn get_from_slot_mut(&mut self, slot: ItemSlot) -> &mut [Item] {
+        return self.rotatable_items.get_mut(&slot).map_or(&mut [], |items| items.as_mut_slice());
+    }
     pub fn iter_variants(&self) -> RotateVariantsGenerator {
         return RotateVariantsGenerator{rotatables: &self, to_skip: 0};
     }
 }
 
+impl From<Vec<Item>> for Rotatables {
+    fn from(items: Vec<Item>) -> Self {
+        let mut res = Self::new();
+        for item in items {
+            res.rotate(item);
+        }
+        return res;
+    }
+}
+
+impl From<Rotatables> for Vec<Item> {
+    fn from(rotatables: Rotatables) -> Self {
+        let mut res = Vec::new();
+        for slot in rotatables.slots_in_rotation() {
+            res.extend(rotatables.get_from_slot(*slot).iter().cloned());
+        }
+        return res;
+    }
+}
+
 pub struct RotateVariantsGenerator<'a> {
     to_skip: usize,
     rotatables: &'a Rotatables,
@@ -81,7 +144,8 @@ impl<'a> Iterator for RotateVariantsGenerator<'a> {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct CurStats {
     stats: HashMap<Stat, u32>,
 }
@@ -109,6 +173,17 @@ impl CurStats {
         }
         return res;
     }
+    // Like to_string, with what every combat rating converts to.
+    pub fn to_string_with_ratings(&self, ratings: &RatingTable) -> String {
+        let mut res = String::with_capacity(200);
+        for (k, v) in self.stats.iter() {
+            match (ratings.to_units(*k, *v), RatingTable::unit_of(*k)) {
+                (Some(units), Some(unit)) => res += &format!("{}: {} ({:.2}{})\n", k, v, units, unit),
+                _ => res += &format!("{}: {}\n", k, v),
+            }
+        }
+        return res;
+    }
     pub fn iter_stats(&self) -> std::collections::hash_map::Iter<Stat, u32> {
         return self.stats.iter();
     }
@@ -122,10 +197,7 @@ impl CurStats {
     pub fn calculate_gain(&self, reqs: &[Requirement]) -> f64 {
         let mut gain: f64 = 0.0;
         for req in reqs {
-            match req {
-                Requirement::RequirementCap(cap) => gain += cap.calculate_gain(self.get_stat_val(cap.get_stat())),
-                Requirement::RequirementWeighted(weighted) => gain += weighted.calculate_gain(self.get_stat_val(weighted.get_stat())),
-            }
+            gain += req.gain(self);
         }
         return gain;
     }
@@ -134,7 +206,8 @@ impl CurStats {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::RequirementWeighted;
+    use crate::items::SetBonus;
+    use crate::{Bonus, Bonuses, RequirementWeighted};
 
     #[test]
     fn gain_correct() {
@@ -155,4 +228,26 @@ mod tests {
         let res = sp.sum_of(&sp2);
         assert!(res.get_stat_val(Stat::CritRate) == 10 && res.get_stat_val(Stat::HasteRate) == 10 && res.get_stat_val(Stat::Agility) == 20);
     }
+    #[test]
+    fn set_bonuses_from_rotation() {
+        let set = ItemSet::new(String::from("tier"), vec![SetBonus::new(2, Bonuses::new(vec![Bonus::new(Stat::Agility, 20)])), SetBonus::new(4, Bonuses::new(vec![Bonus::new(Stat::CritRate, 40)]))]);
+        let mut build = ItemBuild::new();
+        for slot in [ItemSlot::Head, ItemSlot::Chest, ItemSlot::Legs] {
+            let mut item = Item::new(slot.to_string(), slot, Bonuses::new(vec![]), vec![], None, None);
+            item.set_item_set(Some(String::from("tier")));
+            build.lock_item(item);
+        }
+        let mut stats = CurStats::new();
+        build.apply_set_bonuses(std::slice::from_ref(&set), &[ItemSlot::Legs], &mut stats);
+        assert!(stats.get_stat_val(Stat::Agility) == 0 && stats.get_stat_val(Stat::CritRate) == 0);
+        build.apply_set_bonuses(std::slice::from_ref(&set), &[ItemSlot::Chest, ItemSlot::Legs], &mut stats);
+        assert!(stats.get_stat_val(Stat::Agility) == 20 && stats.get_stat_val(Stat::CritRate) == 0);
+        // Two locked pieces and two rotated ones reach the four piece bonus, the two piece one is in the current stats already.
+        let mut feet = Item::new(String::from("feet"), ItemSlot::Feet, Bonuses::new(vec![]), vec![], None, None);
+        feet.set_item_set(Some(String::from("tier")));
+        build.lock_item(feet);
+        let mut stats = CurStats::new();
+        build.apply_set_bonuses(&[set], &[ItemSlot::Chest, ItemSlot::Legs], &mut stats);
+        assert!(stats.get_stat_val(Stat::Agility) == 0 && stats.get_stat_val(Stat::CritRate) == 40);
+    }
 }
\ No newline at end of file
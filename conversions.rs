@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+use crate::Stat;
+
+// Stat that comes with every point of another one, like attack power from strength. Ratio is in the units of to, so crit from agility is crit rating.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StatConversion {
+    from: Stat,
+    to: Stat,
+    ratio: f64,
+}
+
+// Talents and buffs like +10% to all stats. Several multipliers on one stat stack multiplicatively.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StatMultiplier {
+    stat: Stat,
+    factor: f64,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "ConversionsSpec", into = "ConversionsSpec")]
+pub struct StatConversions {
+    multipliers: Vec<StatMultiplier>,
+    conversions: Vec<StatConversion>,
+}
+
+impl StatConversions {
+    pub fn new() -> Self {
+        return Self{multipliers: vec![], conversions: vec![]};
+    }
+    pub fn is_empty(&self) -> bool {
+        return self.multipliers.is_empty() && self.conversions.is_empty();
+    }
+    pub fn multiply(&mut self, stat: Stat, factor: f64) -> () {
+        self.multipliers.push(StatMultiplier{stat, factor});
+    }
+    // Conversions don't chain: returns false without adding it if from is converted into or to is converted from.
+    pub fn convert(&mut self, from: Stat, to: Stat, ratio: f64) -> bool {
+        if from == to || self.conversions.iter().any(|conv| conv.to == from || conv.from == to) { return false; }
+        self.conversions.push(StatConversion{from, to, ratio});
+        return true;
+    }
+    // Like convert, but when to is converted further from goes straight to those stats instead, so nothing chains. A requirement
+    // on to itself then doesn't see from. Returns false if from is converted into.
+    pub fn convert_through(&mut self, from: Stat, to: Stat, ratio: f64) -> bool {
+        if from == to || self.conversions.iter().any(|conv| conv.to == from) { return false; }
+        let further: Vec<StatConversion> = self.conversions.iter().filter(|conv| conv.from == to).cloned().collect();
+        if further.is_empty() { return self.convert(from, to, ratio); }
+        let multiplier = self.get_multiplier(to);
+        for conv in further {
+            self.conversions.push(StatConversion{from, to: conv.to, ratio: ratio * conv.ratio * multiplier});
+        }
+        return true;
+    }
+    pub fn get_multiplier(&self, stat: Stat) -> f64 {
+        return self.multipliers.iter().filter(|mult| mult.stat == stat).map(|mult| mult.factor).product();
+    }
+    // How much one point of stat adds to the final value of target. Multipliers apply to a stat before it's converted and to
+    // everything converted into a stat, like a bonus to attack power also counting the attack power from strength.
+    pub fn coefficient(&self, stat: Stat, target: Stat) -> f64 {
+        let ratio: f64 = self.conversions.iter().filter(|conv| conv.from == stat && conv.to == target).map(|conv| conv.ratio).sum();
+        return self.get_multiplier(target) * (if stat == target { 1.0 } else { ratio * self.get_multiplier(stat) });
+    }
+    // Every stat with a share in the final value of target, with its coefficient.
+    pub fn sources_of(&self, target: Stat) -> Vec<(Stat, f64)> {
+        let mut res = vec![(target, self.coefficient(target, target))];
+        for conv in self.conversions.iter().filter(|conv| conv.to == target) {
+            if !res.iter().any(|(stat, _)| *stat == conv.from) { res.push((conv.from, self.coefficient(conv.from, target))); }
+        }
+        return res;
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ConversionsSpec {
+    #[serde(default)]
+    multipliers: Vec<StatMultiplier>,
+    #[serde(default)]
+    conversions: Vec<StatConversion>,
+}
+
+impl TryFrom<ConversionsSpec> for StatConversions {
+    type Error = String;
+    fn try_from(spec: ConversionsSpec) -> Result<Self, Self::Error> {
+        let mut res = Self{multipliers: spec.multipliers, conversions: vec![]};
+        for conv in spec.conversions {
+            if !res.convert(conv.from, conv.to, conv.ratio) { return Err(format!("{} to {} would chain with another conversion", conv.from, conv.to)); }
+        }
+        return Ok(res);
+    }
+}
+
+impl From<StatConversions> for ConversionsSpec {
+    fn from(conversions: StatConversions) -> Self {
+        return Self{multipliers: conversions.multipliers, conversions: conversions.conversions};
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kings_and_strength() {
+        let mut conversions = StatConversions::new();
+        conversions.multiply(Stat::Strength, 1.1);
+        conversions.multiply(Stat::AttackPower, 1.1);
+        assert!(conversions.convert(Stat::Strength, Stat::AttackPower, 2.0));
+        assert!(!conversions.convert(Stat::AttackPower, Stat::CritRate, 1.0));
+        let sources = conversions.sources_of(Stat::AttackPower);
+        assert_eq!(sources.len(), 2);
+        assert!((sources[1].1 - 2.0 * 1.1 * 1.1).abs() < 1e-9);
+        assert_eq!(conversions.coefficient(Stat::Agility, Stat::Agility), 1.0);
+        assert!(conversions.convert_through(Stat::HasteRate, Stat::Strength, 0.5));
+        assert!((conversions.coefficient(Stat::HasteRate, Stat::AttackPower) - 0.5 * 1.1 * 2.0 * 1.1).abs() < 1e-9);
+    }
+}
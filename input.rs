@@ -0,0 +1,395 @@
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::char::{CurStats, ItemBuild, Rotatables};
+use crate::simulator::{Requirement, Simulator};
+use crate::conversions::StatConversions;
+use crate::catalog::Catalog;
+use crate::items::ItemSet;
+use crate::optimizer::BestBonusFinder;
+use crate::presets::Preset;
+use crate::ratings::RatingTable;
+use crate::race::{Race, WeaponType};
+use crate::procs::ProcBonus;
+use crate::{ItemSlot, Profession};
+
+#[derive(Debug)]
+pub enum InputError {
+    Io { path: String, source: std::io::Error },
+    Parse { line: usize, message: String },
+    Serialize { message: String },
+}
+
+impl std::fmt::Display for InputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InputError::Io { path, source } => write!(f, "can't access {}: {}", path, source),
+            InputError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            InputError::Serialize { message } => write!(f, "can't serialize input: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for InputError {}
+
+// Everything Simulator::new needs, in a form that can be written to and read from TOML or JSON.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OptimizationInput {
+    // Requirements, conversions and catalog to start from, the fields below override them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preset: Option<Preset>,
+    // Character level, for caps given in percent or skill.
+    #[serde(default)]
+    level: RatingTable,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    race: Option<Race>,
+    // Main hand weapon type, for racial expertise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    weapon: Option<WeaponType>,
+    // With the locked items on, set bonuses they reach included, and nothing in the rotated slots.
+    stats: CurStats,
+    #[serde(default = "ItemBuild::new")]
+    build: ItemBuild,
+    #[serde(default)]
+    rotatables: Option<Rotatables>,
+    // Without a preset there has to be at least one.
+    #[serde(default)]
+    requirements: Vec<Requirement>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sets: Vec<ItemSet>,
+    // Unlock profession gems, enchantments and sockets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    professions: Vec<Profession>,
+    // Seconds, for on-use effects of items and enchantments. Without it they count as in a fight that never ends.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fight_length: Option<f64>,
+    // Procs of the equipped gear, averaged with the character's haste.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    procs: Vec<ProcBonus>,
+    // Requirement weights are on final stats, after these.
+    #[serde(default, skip_serializing_if = "StatConversions::is_empty")]
+    conversions: StatConversions,
+}
+
+impl OptimizationInput {
+    pub fn new(stats: CurStats, requirements: Vec<Requirement>, build: ItemBuild, rotatables: Option<Rotatables>) -> Self {
+        return Self{preset: None, level: RatingTable::default(), race: None, weapon: None, stats, build, rotatables, requirements, sets: vec![], professions: vec![], fight_length: None, procs: vec![], conversions: StatConversions::new()};
+    }
+    pub fn from_toml_str(src: &str) -> Result<Self, InputError> {
+        return toml::from_str(src).map_err(|err| InputError::Parse{
+            line: err.span().map_or(0, |span| src[..span.start.min(src.len())].matches('\n').count() + 1),
+            message: err.message().to_owned(),
+        });
+    }
+    pub fn from_json_str(src: &str) -> Result<Self, InputError> {
+        return serde_json::from_str(src).map_err(|err| InputError::Parse{line: err.line(), message: err.to_string()});
+    }
+    // Files ending with .json are read as JSON, anything else as TOML.
+    pub fn from_file(path: &Path) -> Result<Self, InputError> {
+        let src = std::fs::read_to_string(path).map_err(|source| InputError::Io{path: path.display().to_string(), source})?;
+        if is_json(path) { return Self::from_json_str(&src); } else { return Self::from_toml_str(&src); }
+    }
+    pub fn to_toml_string(&self) -> Result<String, InputError> {
+        return toml::to_string(self).map_err(|err| InputError::Serialize{message: err.to_string()});
+    }
+    pub fn to_json_string(&self) -> Result<String, InputError> {
+        return serde_json::to_string_pretty(self).map_err(|err| InputError::Serialize{message: err.to_string()});
+    }
+    pub fn to_file(&self, path: &Path) -> Result<(), InputError> {
+        let res = if is_json(path) { self.to_json_string()? } else { self.to_toml_string()? };
+        return std::fs::write(path, res).map_err(|source| InputError::Io{path: path.display().to_string(), source});
+    }
+    pub fn set_build(&mut self, build: ItemBuild, rotatables: Option<Rotatables>) -> () {
+        self.build = build;
+        self.rotatables = rotatables;
+    }
+    // Toggles the extra prismatic socket on the locked item and every rotatable candidate in the slot. Returns false if there's no such item.
+    pub fn set_extra_socket(&mut self, slot: ItemSlot, enabled: bool) -> bool {
+        let mut found = false;
+        if let Some(item) = self.build.get_item_mut(slot) {
+            item.set_extra_socket(enabled);
+            found = true;
+        }
+        if let Some(ref mut rotatables) = self.rotatables {
+            for item in rotatables.get_from_slot_mut(slot) {
+                item.set_extra_socket(enabled);
+                found = true;
+            }
+        }
+        return found;
+    }
+    pub fn get_stats(&self) -> &CurStats { return &self.stats; }
+    pub fn get_build(&self) -> &ItemBuild { return &self.build; }
+    pub fn get_rotatables(&self) -> &Option<Rotatables> { return &self.rotatables; }
+    pub fn get_requirements(&self) -> &[Requirement] { return &self.requirements; }
+    pub fn get_preset(&self) -> &Option<Preset> { return &self.preset; }
+    pub fn set_preset(&mut self, preset: Option<Preset>) -> () {
+        self.preset = preset;
+    }
+    pub fn get_ratings(&self) -> &RatingTable { return &self.level; }
+    pub fn set_ratings(&mut self, ratings: RatingTable) -> () {
+        self.level = ratings;
+    }
+    pub fn get_race(&self) -> Option<Race> { return self.race; }
+    pub fn get_weapon(&self) -> Option<WeaponType> { return self.weapon; }
+    pub fn set_race(&mut self, race: Option<Race>, weapon: Option<WeaponType>) -> () {
+        self.race = race;
+        self.weapon = weapon;
+    }
+    pub fn get_conversions(&self) -> &StatConversions { return &self.conversions; }
+    pub fn set_conversions(&mut self, conversions: StatConversions) -> () {
+        self.conversions = conversions;
+    }
+    pub fn get_item_sets(&self) -> &[ItemSet] { return &self.sets; }
+    pub fn set_item_sets(&mut self, sets: Vec<ItemSet>) -> () {
+        self.sets = sets;
+    }
+    pub fn get_professions(&self) -> &[Profession] { return &self.professions; }
+    pub fn set_professions(&mut self, professions: Vec<Profession>) -> () {
+        self.professions = professions;
+    }
+    pub fn get_fight_length(&self) -> Option<f64> { return self.fight_length; }
+    pub fn set_fight_length(&mut self, fight_length: Option<f64>) -> () {
+        self.fight_length = fight_length;
+    }
+    pub fn get_procs(&self) -> &[ProcBonus] { return &self.procs; }
+    pub fn set_procs(&mut self, procs: Vec<ProcBonus>) -> () {
+        self.procs = procs;
+    }
+    pub fn into_simulator(mut self) -> Simulator {
+        for profession in self.professions.clone() {
+            for slot in profession.extra_socket_slots() {
+                self.set_extra_socket(*slot, true);
+            }
+        }
+        let requirements = match self.preset {
+            Some(ref preset) => preset.merge_requirements(self.requirements),
+            None => self.requirements,
+        };
+        let mut res = Simulator::new(self.stats, requirements, self.build, self.rotatables);
+        res.set_ratings(self.level);
+        res.set_race(self.race, self.weapon);
+        match self.preset {
+            Some(ref preset) if self.conversions.is_empty() => res.set_conversions(preset.get_conversions().clone()),
+            _ => res.set_conversions(self.conversions),
+        }
+        if let Some(ref preset) = self.preset {
+            res.set_bonus_finder(BestBonusFinder::from_catalog(&preset.catalog(&Catalog::default_catalog())));
+        }
+        res.set_professions(self.professions);
+        res.set_item_sets(self.sets);
+        res.set_procs(self.procs);
+        res.set_fight_length(self.fight_length);
+        return res;
+    }
+    // Gain each profession adds on its own to the build without any, most valuable first. The optimization runs with the
+    // catalog's gems, enchantments and consumables when one is given.
+    pub fn compare_professions(&self, catalog: Option<&Catalog>, enable_gems: bool, enable_chants: bool, enable_consumables: bool, enable_prechant: bool) -> (f64, Vec<(Profession, f64)>) {
+        let gain_with = |professions: Vec<Profession>| {
+            let mut input = self.clone();
+            input.set_professions(professions);
+            let mut sim = input.into_simulator();
+            if let Some(catalog) = catalog { sim.set_bonus_finder(BestBonusFinder::from_catalog(catalog)); }
+            sim.run(enable_gems, enable_chants, enable_consumables, enable_prechant);
+            return sim.get_gain();
+        };
+        let base = gain_with(vec![]);
+        let mut res: Vec<(Profession, f64)> = Profession::ALL.iter().map(|profession| (*profession, gain_with(vec![*profession]) - base)).collect();
+        res.sort_by(|a, b| b.1.total_cmp(&a.1));
+        return (base, res);
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    return path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::{Enchantment, Gem, GemSocket, Item, SetBonus};
+    use crate::{Bonus, Bonuses, Color, RequirementCap, RequirementWeighted, Stat};
+
+    fn sample() -> OptimizationInput {
+        let mut stats = CurStats::new();
+        stats.set_stat(Stat::HitRate, 201);
+        let mut build = ItemBuild::new();
+        let mut feet = Item::new(String::from("feet"), ItemSlot::Feet, Bonuses::new(vec![Bonus::new(Stat::Agility, 30)]), vec![GemSocket::new(Color::Yellow), GemSocket::new(Color::Blue)], Some(Bonus::new(Stat::Agility, 6)), Some(Enchantment::new(ItemSlot::Feet, Bonuses::new(vec![Bonus::new(Stat::HitRate, 12)]), String::from("precision"))));
+        feet.get_socket_mut(0).set_gem(&Gem::new(vec![Color::Red, Color::Yellow], Bonuses::new(vec![Bonus::new(Stat::Agility, 10), Bonus::new(Stat::CritRate, 10)]), String::from("deadly")));
+        build.lock_item(feet);
+        let mut rotatables = Rotatables::new();
+        rotatables.rotate(Item::new(String::from("ring1_1"), ItemSlot::Ring1, Bonuses::new(vec![]), vec![GemSocket::new(Color::Red)], None, None));
+        let mut ring = Item::new(String::from("ring1_2"), ItemSlot::Ring1, Bonuses::new(vec![]), vec![], None, None);
+        ring.set_item_set(Some(String::from("band")));
+        rotatables.rotate(ring);
+        let reqs = vec![Requirement::RequirementCap(RequirementCap::new(Stat::HitRate, 230, 2.19)), Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.91))];
+        let mut res = OptimizationInput::new(stats, reqs, build, Some(rotatables));
+        res.set_item_sets(vec![ItemSet::new(String::from("band"), vec![SetBonus::new(2, Bonuses::new(vec![Bonus::new(Stat::HitRate, 10)]))])]);
+        return res;
+    }
+    #[test]
+    fn toml_round_trip() {
+        let input = sample();
+        let restored = OptimizationInput::from_toml_str(&input.to_toml_string().unwrap()).unwrap();
+        assert_eq!(serde_json::to_value(&input).unwrap(), serde_json::to_value(&restored).unwrap());
+        let feet = restored.get_build().get_item(ItemSlot::Feet).as_ref().unwrap();
+        assert!(feet.get_sockets()[0].get_color() == Color::Yellow && feet.get_sockets()[0].get_gem().as_ref().unwrap().get_name() == "deadly");
+        assert!(feet.get_sockets()[1].is_empty() && feet.get_enchantment().as_ref().unwrap().get_name() == "precision");
+        assert!(restored.get_item_sets()[0].get_name() == "band" && restored.get_rotatables().as_ref().unwrap().get_from_slot(ItemSlot::Ring1)[1].get_item_set() == Some("band"));
+    }
+    #[test]
+    fn json_round_trip() {
+        let input = sample();
+        let restored = OptimizationInput::from_json_str(&input.to_json_string().unwrap()).unwrap();
+        assert_eq!(serde_json::to_value(&input).unwrap(), serde_json::to_value(&restored).unwrap());
+    }
+    #[test]
+    fn custom_requirement_not_written() {
+        use std::sync::Arc;
+        use crate::{GainFunction, RequirementCustom};
+        struct Agility;
+        impl GainFunction for Agility {
+            fn gain(&self, stats: &CurStats) -> f64 {
+                return stats.get_stat_val(Stat::Agility) as f64;
+            }
+        }
+        let custom = Requirement::RequirementCustom(RequirementCustom::new(String::from("agility"), Arc::new(Agility)));
+        let input = OptimizationInput::new(CurStats::new(), vec![custom], ItemBuild::new(), None);
+        for res in [input.to_toml_string(), input.to_json_string()] {
+            match res {
+                Err(InputError::Serialize{message}) => assert!(message.contains("custom requirement agility")),
+                _ => panic!("Custom requirements can't be written!"),
+            }
+        }
+    }
+    #[test]
+    fn bad_input_line() {
+        let src = "requirements = []\n\n[stats]\nHitRate = -3\n";
+        match OptimizationInput::from_toml_str(src) {
+            Err(InputError::Parse{line, ..}) => assert_eq!(line, 4),
+            _ => panic!("Negative stats must be rejected!"),
+        }
+    }
+    #[test]
+    fn extra_socket_everywhere() {
+        let mut input = sample();
+        assert!(input.set_extra_socket(ItemSlot::Ring1, true) && input.set_extra_socket(ItemSlot::Feet, true));
+        assert!(!input.set_extra_socket(ItemSlot::Head, true));
+        assert!(input.get_rotatables().as_ref().unwrap().get_from_slot(ItemSlot::Ring1).iter().all(|item| item.has_extra_socket()));
+        assert_eq!(input.get_build().get_item(ItemSlot::Feet).as_ref().unwrap().get_sockets().len(), 3);
+    }
+    #[test]
+    fn caps_in_units() {
+        let src = "level = 80\nrequirements = [{ type = \"cap\", stat = \"HitRate\", percent = 8.0, weight = 2.19 }, { type = \"cap\", stat = \"ExpertiseRate\", skill = 26.0, weight = 2.19 }]\n\n[stats]\nHitRate = 200\n\n[[rotatables]]\nname = \"ring\"\nslot = \"Ring1\"\nstats = [{ stat = \"HitRate\", val = 100 }, { stat = \"ExpertiseRate\", val = 300 }]\n";
+        let input = OptimizationInput::from_toml_str(src).unwrap();
+        let restored = OptimizationInput::from_toml_str(&input.to_toml_string().unwrap()).unwrap();
+        match &restored.get_requirements()[1] {
+            Requirement::RequirementCap(cap) => assert_eq!(cap.get_units(), Some(26.0)),
+            _ => panic!("Requirements must keep their order!"),
+        }
+        let mut sim = restored.into_simulator();
+        sim.score();
+        // At level 80 the caps are 263 hit and 214 expertise rating, so the ring only counts up to them.
+        assert_eq!(sim.get_gain(), (63 + 214) as f64 * 2.19);
+        let src = "requirements = [{ type = \"cap\", stat = \"Agility\", percent = 5.0, weight = 1.0 }]\n\n[stats]\n";
+        assert!(OptimizationInput::from_toml_str(src).is_err());
+    }
+    #[test]
+    fn racial_caps() {
+        let src = "race = \"Draenei\"\nweapon = \"Sword\"\nrequirements = [{ type = \"cap\", stat = \"HitRate\", percent = 8.0, weight = 1.0 }, { type = \"cap\", stat = \"ExpertiseRate\", val = 214, weight = 1.0 }]\n\n[stats]\n\n[[rotatables]]\nname = \"ring\"\nslot = \"Ring1\"\nstats = [{ stat = \"HitRate\", val = 300 }, { stat = \"ExpertiseRate\", val = 300 }]\n";
+        let mut input = OptimizationInput::from_toml_str(src).unwrap();
+        let mut sim = input.clone().into_simulator();
+        sim.score();
+        // Heroic Presence leaves 7% hit, 230 rating. Draenei get nothing from swords.
+        assert_eq!(sim.get_gain(), (230 + 214) as f64);
+        input.set_race(Some(Race::Human), Some(WeaponType::Sword));
+        let mut sim = input.into_simulator();
+        sim.score();
+        // Three skill of expertise are 25 rating at level 80.
+        assert_eq!(sim.get_gain(), (263 + 189) as f64);
+    }
+    #[test]
+    fn haste_feeds_procs() {
+        let src = "requirements = [{ type = \"weighted\", stat = \"AttackPower\", weight = 1.0 }]\nprocs = [{ stat = \"AttackPower\", val = 400, duration = 15.0, trigger = { type = \"ppm\", ppm = 1.0 } }]\n\n[stats]\nHasteRate = 100\n\n[[rotatables]]\nname = \"ring\"\nslot = \"Ring1\"\nstats = [{ stat = \"HasteRate\", val = 200 }]\n";
+        let input = OptimizationInput::from_toml_str(src).unwrap();
+        let (_, slope) = input.get_procs()[0].average(100.0, input.get_ratings());
+        let mut sim = input.into_simulator();
+        sim.score();
+        // The ring's haste only counts through the attack power it adds to the proc.
+        assert!(slope > 0.0 && (sim.get_gain() - 200.0 * slope).abs() < 1e-9);
+    }
+    #[test]
+    fn expertise_regions() {
+        let src = |expertise: u32| format!("requirements = [{{ type = \"piecewise\", stat = \"ExpertiseRate\", regions = [{{ val = 100, weight = 2.0 }}, {{ val = 200, weight = 1.0 }}] }}, {{ type = \"weighted\", stat = \"Agility\", weight = 0.6 }}]\n\n[stats]\nExpertiseRate = {}\n\n[[build]]\nname = \"ring\"\nslot = \"Ring1\"\nsockets = [{{ color = \"Red\" }}]\n", expertise);
+        let input = OptimizationInput::from_toml_str(&src(150)).unwrap();
+        let restored = OptimizationInput::from_toml_str(&input.to_toml_string().unwrap()).unwrap();
+        assert_eq!(restored.get_requirements()[0].to_string(), "ExpertiseRate, weight 2 up to 100, weight 1 up to 200, weight 0 after");
+        let gem_for = |input: OptimizationInput| {
+            let mut sim = input.into_simulator();
+            sim.run(true, false, false, true);
+            return (sim.get_solution_items()[0].get_sockets()[0].get_gem().as_ref().unwrap().get_name().to_owned(), sim.get_gain());
+        };
+        assert_eq!(gem_for(restored), (String::from("precise"), 20.0));
+        // Only 10 points are left before the last breakpoint, so agility beats expertise.
+        assert_eq!(gem_for(OptimizationInput::from_toml_str(&src(190)).unwrap()), (String::from("delicate"), 12.0));
+        let src = "requirements = [{ type = \"piecewise\", stat = \"ExpertiseRate\", regions = [{ val = 200, weight = 2.0 }, { val = 100, weight = 1.0 }] }]\n\n[stats]\n";
+        assert!(OptimizationInput::from_toml_str(src).is_err());
+    }
+    #[test]
+    fn hit_minimum() {
+        let src = |hit: u32| format!("requirements = [{{ type = \"minimum\", stat = \"HitRate\", val = {} }}, {{ type = \"weighted\", stat = \"Agility\", weight = 1.0 }}]\n\n[stats]\n\n[[build]]\nname = \"head\"\nslot = \"Head\"\nsockets = [{{ color = \"Red\" }}]\n\n[[build]]\nname = \"neck\"\nslot = \"Neck\"\nsockets = [{{ color = \"Red\" }}]\n\n[[build]]\nname = \"ring\"\nslot = \"Ring1\"\nsockets = [{{ color = \"Red\" }}]\n", hit);
+        let run = |hit: u32| {
+            let mut sim = OptimizationInput::from_toml_str(&src(hit)).unwrap().into_simulator();
+            sim.run(true, false, false, true);
+            return sim;
+        };
+        // Two gems' worth of hit have to come before agility.
+        let sim = run(40);
+        assert!(sim.has_solution() && sim.get_gain() == 20.0);
+        assert!(sim.report().unwrap().get_stat_growth().get_stat_val(Stat::HitRate) >= 40);
+        // Three sockets give 60 hit at most.
+        let sim = run(80);
+        assert!(!sim.has_solution());
+        assert_eq!(sim.get_shortfall(), &[(Stat::HitRate, 20.0)]);
+    }
+    #[test]
+    fn haste_breakpoint() {
+        let src = |gain: f64| format!("requirements = [{{ type = \"breakpoint\", stat = \"HasteRate\", thresholds = [{{ val = 40, gain = {:?} }}, {{ val = 200, gain = 100.0 }}] }}, {{ type = \"weighted\", stat = \"Agility\", weight = 1.0 }}]\n\n[stats]\n\n[[build]]\nname = \"head\"\nslot = \"Head\"\nsockets = [{{ color = \"Red\" }}]\n\n[[build]]\nname = \"neck\"\nslot = \"Neck\"\nsockets = [{{ color = \"Red\" }}]\n\n[[build]]\nname = \"ring\"\nslot = \"Ring1\"\nsockets = [{{ color = \"Red\" }}]\n", gain);
+        let input = OptimizationInput::from_toml_str(&src(30.0)).unwrap();
+        let restored = OptimizationInput::from_toml_str(&input.to_toml_string().unwrap()).unwrap();
+        assert_eq!(restored.get_requirements()[0].to_string(), "HasteRate, gain 30 at 40, gain 100 at 200");
+        let gain = |input: OptimizationInput| {
+            let mut sim = input.into_simulator();
+            sim.run(true, false, false, true);
+            return (sim.get_gain(), sim.report().unwrap().get_stat_growth().get_stat_val(Stat::HasteRate));
+        };
+        // Two haste gems are worth less than agility unless they reach the first threshold for more. The second one is out of reach.
+        assert_eq!(gain(restored), (60.0, 0));
+        assert_eq!(gain(OptimizationInput::from_toml_str(&src(50.0)).unwrap()), (70.0, 40));
+    }
+    #[test]
+    fn profession_perks() {
+        let src = "professions = [\"Blacksmithing\"]\nrequirements = [{ type = \"weighted\", stat = \"Agility\", weight = 1.0 }, { type = \"weighted\", stat = \"AttackPower\", weight = 1.0 }]\n\n[stats]\n\n[[build]]\nname = \"ring\"\nslot = \"Ring1\"\n\n[[build]]\nname = \"bracer\"\nslot = \"Bracer\"\nsockets = [{ color = \"Red\" }]\n\n[[build]]\nname = \"gloves\"\nslot = \"Gloves\"\n";
+        let input = OptimizationInput::from_toml_str(src).unwrap();
+        let mut sim = input.clone().into_simulator();
+        sim.run(true, true, false, true);
+        assert!(sim.get_solution_items().iter().all(|item| item.get_slot() == ItemSlot::Ring1 || item.has_extra_socket()));
+        let (base, gains) = input.compare_professions(None, true, true, false, true);
+        let gain_of = |profession: Profession| gains.iter().find(|(known, _)| *known == profession).unwrap().1;
+        // A delicate gem and agility on the bracer, crusher on the gloves.
+        assert_eq!(base, 84.0);
+        assert_eq!(gains[0], (Profession::Leatherworking, 110.0));
+        assert!(gain_of(Profession::Enchanting) == 40.0 && gain_of(Profession::Blacksmithing) == 40.0 && gain_of(Profession::Jewelcrafting) == 14.0);
+        assert_eq!(gain_of(Profession::Tailoring), 0.0);
+    }
+    #[test]
+    fn preset_by_name() {
+        let input = OptimizationInput::from_toml_str("preset = \"combat-rogue\"\n\n[stats]\nAgility = 1000\n").unwrap();
+        assert_eq!(input.get_preset().as_ref().unwrap().get_name(), "combat-rogue");
+        assert!(input.to_toml_string().unwrap().starts_with("preset = \"combat-rogue\""));
+        match OptimizationInput::from_toml_str("requirements = []\npreset = \"paladin\"\n\n[stats]\n") {
+            Err(InputError::Parse{line, ..}) => assert_eq!(line, 2),
+            _ => panic!("Unknown presets must be rejected!"),
+        }
+    }
+}
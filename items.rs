@@ -1,12 +1,17 @@
-use crate::{Bonus, Bonuses, ItemSlot};
+use crate::{Bonus, Bonuses, ItemSlot, Profession};
 use crate::char::CurStats;
+use crate::procs::OnUse;
+use serde::{Deserialize, Serialize};
 
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub enum Color {
     Red,
     Blue,
     Yellow,
+    Meta,
+    // Socket-only color: takes any non-meta gem and never affects the socket bonus.
+    Prismatic,
 }
 
 impl std::fmt::Display for Color {
@@ -15,25 +20,49 @@ impl std::fmt::Display for Color {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Item {
     name: String,
     slot: ItemSlot,
+    #[serde(default)]
     stats: Bonuses,
+    #[serde(default)]
     sockets: Vec<GemSocket>,
+    #[serde(default)]
     socket_bonus: Option<Bonus>,
+    #[serde(default)]
     enchant: Option<Enchantment>,
+    #[serde(default)]
+    set: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    on_use: Vec<OnUse>,
+    // Whether the last of sockets is the added prismatic one, see set_extra_socket.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    extra_socket: bool,
 }
 
 impl Item {
     pub fn new(name: String, slot: ItemSlot, stats: Bonuses, sockets: Vec<GemSocket>, socket_bonus: Option<Bonus>, enchant: Option<Enchantment>) -> Self {
-        return Self{name, slot, stats, sockets, socket_bonus, enchant};
+        return Self{name, slot, stats, sockets, socket_bonus, enchant, set: None, on_use: vec![], extra_socket: false};
     }
+    // Prismatic sockets take no part in the bonus, which needs at least one matched colored socket.
     pub fn sockets_match(&self) -> bool {
+        let mut colored = false;
         for socket in &self.sockets {
+            if socket.get_color() == Color::Prismatic { continue; }
             if !socket.does_match() { return false; }
+            colored = true;
         }
-        return true;
+        return colored;
+    }
+    // Belt buckles and profession sockets add one prismatic socket after the item's own ones.
+    pub fn has_extra_socket(&self) -> bool {
+        return self.extra_socket;
+    }
+    pub fn set_extra_socket(&mut self, enabled: bool) -> () {
+        if enabled && !self.extra_socket { self.sockets.push(GemSocket::new(Color::Prismatic)); }
+        if !enabled && self.extra_socket { self.sockets.pop(); }
+        self.extra_socket = enabled;
     }
     pub fn get_name(&self) -> &str {
         return &self.name;
@@ -53,6 +82,9 @@ impl Item {
     pub fn get_socket_bonus(&self) -> &Option<Bonus> {
         return &self.socket_bonus;
     }
+    pub fn is_socket_bonus_active(&self) -> bool {
+        return self.socket_bonus.is_some() && self.sockets_match();
+    }
     pub fn apply_socket_bonus(&self, cur_stats: &mut CurStats) -> () {
         if let Some(ref bonus) = self.socket_bonus {
             bonus.apply_bonus(cur_stats);
@@ -68,27 +100,146 @@ impl Item {
     pub fn remove_enchantment(&mut self) -> () {
         self.enchant = None;
     }
+    // Name of the ItemSet this item is a piece of.
+    pub fn get_item_set(&self) -> Option<&str> { return self.set.as_deref(); }
+    pub fn set_item_set(&mut self, set: Option<String>) -> () {
+        self.set = set;
+    }
+    // On-use effects of the item itself, like a trinket's. Those of its enchantment belong to the Enchantment.
+    pub fn get_on_use(&self) -> &[OnUse] { return &self.on_use; }
+    pub fn set_on_use(&mut self, on_use: Vec<OnUse>) -> () {
+        self.on_use = on_use;
+    }
+}
+
+// Bonuses granted once the build has this many pieces of the set.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SetBonus {
+    pieces: u32,
+    bonuses: Bonuses,
+}
+
+impl SetBonus {
+    pub fn new(pieces: u32, bonuses: Bonuses) -> Self {
+        return Self{pieces, bonuses};
+    }
+    pub fn get_pieces(&self) -> u32 { return self.pieces; }
+    pub fn get_bonuses(&self) -> &Bonuses { return &self.bonuses; }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ItemSet {
+    name: String,
+    bonuses: Vec<SetBonus>,
+}
+
+impl ItemSet {
+    pub fn new(name: String, bonuses: Vec<SetBonus>) -> Self {
+        return Self{name, bonuses};
+    }
+    pub fn get_name(&self) -> &str { return &self.name; }
+    pub fn get_bonuses(&self) -> &[SetBonus] { return &self.bonuses; }
+}
+
+// Activation rule of a meta gem, checked against the colors of every other gem in the build.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "rule")]
+pub enum MetaActivation {
+    AtLeast { color: Color, count: u32 },
+    MoreThan { more: Color, less: Color },
+}
+
+impl MetaActivation {
+    pub fn is_met(&self, items: &[Item]) -> bool {
+        return self.missing_color(items).is_none();
+    }
+    // Which color one more gem should have to get closer to activation, if any is still needed.
+    pub fn missing_color(&self, items: &[Item]) -> Option<Color> {
+        return self.missing_color_by(|color| count_gem_colors(items, color));
+    }
+    // The same with gems of every color counted elsewhere.
+    pub(crate) fn missing_color_by(&self, count: impl Fn(Color) -> u32) -> Option<Color> {
+        match *self {
+            MetaActivation::AtLeast { color, count: needed } => if count(color) < needed { Some(color) } else { None },
+            MetaActivation::MoreThan { more, less } => if count(more) <= count(less) { Some(more) } else { None },
+        }
+    }
+}
+
+// Multicolored gems count towards every color they have, meta gems don't count at all.
+pub fn count_gem_colors(items: &[Item], color: Color) -> u32 {
+    let mut res = 0;
+    for item in items {
+        for socket in item.get_sockets() {
+            if let Some(ref gem) = socket.gem {
+                if !gem.is_meta() && gem.colors.contains(&color) { res += 1; }
+            }
+        }
+    }
+    return res;
+}
+
+// How many copies of a gem the whole build may have.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum GemLimit {
+    UniqueEquipped,
+    Category { category: String, count: u32 },
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Gem {
     colors: Vec<Color>,
     bonuses: Bonuses,
     name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    activation: Vec<MetaActivation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    limit: Option<GemLimit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profession: Option<Profession>,
 }
 
 impl Gem {
     pub fn new(colors: Vec<Color>, bonuses: Bonuses, name: String) -> Self {
-        return Self{colors, bonuses, name};
+        return Self{colors, bonuses, name, activation: vec![], limit: None, profession: None};
+    }
+    pub fn new_meta(bonuses: Bonuses, name: String, activation: Vec<MetaActivation>) -> Self {
+        return Self{colors: vec![Color::Meta], bonuses, name, activation, limit: None, profession: None};
     }
     pub fn get_colors(&self) -> &Vec<Color> { return &self.colors; }
     pub fn get_bonuses(&self) -> &Bonuses { return &self.bonuses; }
     pub fn get_name(&self) -> &str { return &self.name; } 
+    pub fn get_activation(&self) -> &[MetaActivation] { return &self.activation; }
+    pub fn is_meta(&self) -> bool { return self.colors.contains(&Color::Meta); }
+    pub fn get_limit(&self) -> &Option<GemLimit> { return &self.limit; }
+    pub fn set_limit(&mut self, limit: Option<GemLimit>) -> () { self.limit = limit; }
+    pub fn get_profession(&self) -> Option<Profession> { return self.profession; }
+    pub fn set_profession(&mut self, profession: Option<Profession>) -> () { self.profession = profession; }
+    // Whether one more copy of this gem can join the gems already socketed into the items.
+    pub fn is_within_limit(&self, items: &[Item]) -> bool {
+        let gems = items.iter().flat_map(|item| item.get_sockets().iter()).filter_map(|socket| socket.get_gem().as_ref());
+        match self.limit {
+            None => true,
+            Some(GemLimit::UniqueEquipped) => !gems.into_iter().any(|gem| gem.name == self.name),
+            Some(GemLimit::Category { ref category, count }) => {
+                (gems.filter(|gem| matches!(gem.limit, Some(GemLimit::Category { category: ref other, .. }) if other == category)).count() as u32) < count
+            },
+        }
+    }
+    pub fn is_active(&self, items: &[Item]) -> bool {
+        return self.activation.iter().all(|rule| rule.is_met(items));
+    }
+    // Meta gems only go into meta sockets and nothing else does.
+    pub fn fits(&self, socket: Color) -> bool {
+        return self.is_meta() == (socket == Color::Meta);
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct GemSocket {
     color: Color,
+    #[serde(default)]
     gem: Option<Gem>,
 }
 
@@ -103,43 +254,107 @@ impl GemSocket {
     pub fn set_empty(&mut self) -> () { self.gem = None; }
     pub fn does_match(&self) -> bool {
         if self.gem.is_none() { return false; }
+        if self.color == Color::Prismatic { return self.gem.as_ref().unwrap().fits(self.color); }
         if self.gem.as_ref().unwrap().get_colors().contains(&self.color) { return true; } else { return false; }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Enchantment {
     slot: ItemSlot,
     bonuses: Bonuses,
     name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    on_use: Vec<OnUse>,
+    // Profession the enchantment is limited to, like ring enchants or fur linings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profession: Option<Profession>,
+    // Bonuses with the on-use effects averaged in, see average_on_use.
+    #[serde(skip)]
+    averaged: Option<Bonuses>,
 }
 
 impl Enchantment {
     pub fn new(slot: ItemSlot, bonuses: Bonuses, name: String) -> Self {
-        return Self{slot, bonuses, name};
+        return Self{slot, bonuses, name, on_use: vec![], profession: None, averaged: None};
     }
     pub fn get_slot(&self) -> ItemSlot { return self.slot; }
-    pub fn get_bonuses(&self) -> &Bonuses { return &self.bonuses; }
+    // Includes the on-use effects once they were averaged for a fight.
+    pub fn get_bonuses(&self) -> &Bonuses { return self.averaged.as_ref().unwrap_or(&self.bonuses); }
+    pub fn get_on_use(&self) -> &[OnUse] { return &self.on_use; }
+    pub fn set_on_use(&mut self, on_use: Vec<OnUse>) -> () {
+        self.on_use = on_use;
+        self.averaged = None;
+    }
+    pub fn get_profession(&self) -> Option<Profession> { return self.profession; }
+    pub fn set_profession(&mut self, profession: Option<Profession>) -> () {
+        self.profession = profession;
+    }
+    // Tinkers and the like count with their average over a fight of fight_length seconds.
+    pub fn average_on_use(&mut self, fight_length: Option<f64>) -> () {
+        if self.on_use.is_empty() { return; }
+        let mut bonuses: Vec<Bonus> = self.bonuses.iter().cloned().collect();
+        bonuses.extend(self.on_use.iter().map(|effect| effect.to_bonus(fight_length)));
+        self.averaged = Some(Bonuses::new(bonuses));
+    }
     pub fn get_name(&self) -> &str { return &self.name; } 
 }
 
-#[derive(Clone)]
-pub struct Food {
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum ConsumableCategory {
+    Flask,
+    BattleElixir,
+    GuardianElixir,
+    Food,
+    Scroll,
+}
+
+impl std::fmt::Display for ConsumableCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return std::fmt::Debug::fmt(self, f);
+    }
+}
+
+impl ConsumableCategory {
+    pub const ALL: [ConsumableCategory; 5] = [ConsumableCategory::Flask, ConsumableCategory::BattleElixir, ConsumableCategory::GuardianElixir, ConsumableCategory::Food, ConsumableCategory::Scroll];
+    // Only one consumable of a category is active at a time, and a flask takes the place of both elixirs.
+    pub fn excludes(&self, other: ConsumableCategory) -> bool {
+        match (self, other) {
+            (ConsumableCategory::Flask, ConsumableCategory::BattleElixir | ConsumableCategory::GuardianElixir) => true,
+            (ConsumableCategory::BattleElixir | ConsumableCategory::GuardianElixir, ConsumableCategory::Flask) => true,
+            _ => *self == other,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Consumable {
     name: String,
+    category: ConsumableCategory,
     bonuses: Bonuses,
 }
 
-impl Food {
-    pub fn new(name: String, bonuses: Bonuses) -> Self {
-        return Self{name, bonuses};
+impl Consumable {
+    pub fn new(name: String, category: ConsumableCategory, bonuses: Bonuses) -> Self {
+        return Self{name, category, bonuses};
     }
     pub fn get_bonuses(&self) -> &Bonuses { return &self.bonuses; }
     pub fn get_name(&self) -> &str { return &self.name; }
+    pub fn get_category(&self) -> ConsumableCategory { return self.category; }
+}
+
+// Whether all of the consumables can be active together.
+pub fn is_legal_loadout(consumables: &[Consumable]) -> bool {
+    for (ind, first) in consumables.iter().enumerate() {
+        if consumables[ind + 1..].iter().any(|second| first.category.excludes(second.category)) { return false; }
+    }
+    return true;
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Stat;
 
     #[test]
     fn matching_sockets() {
@@ -150,4 +365,70 @@ mod tests {
         }
         assert!(item.sockets_match());
     }
+    #[test]
+    fn flask_excludes_elixirs() {
+        let make = |category| Consumable::new(String::new(), category, Bonuses::new(vec![]));
+        assert!(is_legal_loadout(&[make(ConsumableCategory::BattleElixir), make(ConsumableCategory::GuardianElixir), make(ConsumableCategory::Food)]));
+        assert!(!is_legal_loadout(&[make(ConsumableCategory::Flask), make(ConsumableCategory::Food), make(ConsumableCategory::GuardianElixir)]));
+        assert!(!is_legal_loadout(&[make(ConsumableCategory::Scroll), make(ConsumableCategory::Scroll)]));
+    }
+    #[test]
+    fn meta_activation() {
+        let blue = Gem::new(vec![Color::Red, Color::Blue], Bonuses::new(vec![]), String::new());
+        let meta = Gem::new_meta(Bonuses::new(vec![]), String::new(), vec![MetaActivation::AtLeast{color: Color::Blue, count: 2}, MetaActivation::MoreThan{more: Color::Blue, less: Color::Yellow}]);
+        let mut item = Item::new(String::new(), ItemSlot::Head, Bonuses::new(vec![]), vec![GemSocket::new(Color::Meta), GemSocket::new(Color::Blue), GemSocket::new(Color::Blue)], None, None);
+        item.get_socket_mut(0).set_gem(&meta);
+        item.get_socket_mut(1).set_gem(&blue);
+        assert!(!meta.is_active(&[item.clone()]));
+        item.get_socket_mut(2).set_gem(&blue);
+        assert!(meta.is_active(&[item.clone()]) && item.sockets_match());
+        assert!(meta.fits(Color::Meta) && !meta.fits(Color::Blue) && !blue.fits(Color::Meta));
+    }
+    #[test]
+    fn prismatic_ignores_socket_bonus() {
+        let red = Gem::new(vec![Color::Red], Bonuses::new(vec![]), String::new());
+        let mut prismatic = Item::new(String::new(), ItemSlot::Feet, Bonuses::new(vec![]), vec![GemSocket::new(Color::Prismatic)], Some(Bonus::new(Stat::Agility, 4)), None);
+        prismatic.get_socket_mut(0).set_gem(&red);
+        assert!(!prismatic.is_socket_bonus_active());
+        let mut item = Item::new(String::new(), ItemSlot::Belt, Bonuses::new(vec![]), vec![GemSocket::new(Color::Red), GemSocket::new(Color::Prismatic)], None, None);
+        item.set_extra_socket(true);
+        item.set_extra_socket(true);
+        assert!(item.has_extra_socket() && item.get_sockets().len() == 3);
+        item.get_socket_mut(0).set_gem(&red);
+        assert!(item.sockets_match());
+        item.get_socket_mut(2).set_gem(&red);
+        assert!(item.get_sockets()[2].does_match() && item.sockets_match());
+        // The item's own prismatic socket stays.
+        item.set_extra_socket(false);
+        assert_eq!(item.get_sockets().len(), 2);
+    }
+    #[test]
+    fn socket_bonus_needs_colored_match() {
+        let red = Gem::new(vec![Color::Red], Bonuses::new(vec![]), String::new());
+        let orange = Gem::new(vec![Color::Red, Color::Yellow], Bonuses::new(vec![]), String::new());
+        let mut item = Item::new(String::new(), ItemSlot::Feet, Bonuses::new(vec![]), vec![GemSocket::new(Color::Red), GemSocket::new(Color::Yellow)], Some(Bonus::new(Stat::Agility, 6)), None);
+        item.set_extra_socket(true);
+        item.get_socket_mut(0).set_gem(&red);
+        item.get_socket_mut(2).set_gem(&red);
+        assert!(!item.is_socket_bonus_active());
+        item.get_socket_mut(1).set_gem(&red);
+        assert!(!item.is_socket_bonus_active());
+        item.get_socket_mut(1).set_gem(&orange);
+        assert!(item.is_socket_bonus_active());
+    }
+    #[test]
+    fn gem_limits() {
+        let mut tear = Gem::new(vec![Color::Red], Bonuses::new(vec![]), String::from("tear"));
+        tear.set_limit(Some(GemLimit::UniqueEquipped));
+        let mut eye = Gem::new(vec![Color::Red], Bonuses::new(vec![]), String::from("eye"));
+        eye.set_limit(Some(GemLimit::Category{category: String::from("Dragon's Eye"), count: 2}));
+        let mut other_eye = eye.clone();
+        other_eye.name = String::from("other eye");
+        let mut item = Item::new(String::new(), ItemSlot::Head, Bonuses::new(vec![]), vec![GemSocket::new(Color::Red), GemSocket::new(Color::Red), GemSocket::new(Color::Red)], None, None);
+        item.get_socket_mut(0).set_gem(&tear);
+        item.get_socket_mut(1).set_gem(&eye);
+        assert!(!tear.is_within_limit(&[item.clone()]) && other_eye.is_within_limit(&[item.clone()]));
+        item.get_socket_mut(2).set_gem(&other_eye);
+        assert!(!eye.is_within_limit(&[item.clone()]));
+    }
 }
\ No newline at end of file
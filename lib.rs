@@ -2,13 +2,30 @@ mod items;
 mod simulator;
 mod char;
 mod optimizer;
+mod catalog;
+mod input;
+mod ratings;
+mod conversions;
+mod presets;
+mod race;
+mod procs;
 
 pub use crate::char::{CurStats, ItemBuild, Rotatables};
-pub use crate::items::{Item, Gem, GemSocket, Color};
-pub use crate::simulator::{Requirement, RequirementCap, RequirementWeighted, Simulator};
+pub use crate::items::{Item, Gem, GemSocket, GemLimit, Color, Enchantment, Consumable, ConsumableCategory, MetaActivation, ItemSet, SetBonus};
+pub use crate::simulator::{BreakpointThreshold, GainFunction, PiecewiseRegion, Requirement, RequirementBreakpoint, RequirementCustom, RequirementCap, RequirementDiminishing, RequirementMinimum, RequirementPiecewise, RequirementWeighted, Simulator, OptimizationResult, ItemResult, SocketResult, ConsumableResult};
+pub use crate::optimizer::BestBonusFinder;
+pub use crate::catalog::{Catalog, CatalogError};
+pub use crate::input::{InputError, OptimizationInput};
+pub use crate::ratings::{RatingTable, RatingUnit};
+pub use crate::conversions::{StatConversion, StatConversions, StatMultiplier};
+pub use crate::presets::{Preset, RelicType};
+pub use crate::race::{Race, WeaponType};
+pub use crate::procs::{OnUse, ProcBonus, ProcTrigger};
 
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum ItemSlot {
     Head,
     Neck,
@@ -35,9 +52,29 @@ impl std::fmt::Display for ItemSlot {
     }
 }
 
+impl std::str::FromStr for ItemSlot {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return ITEM_SLOTS_ORDER.iter().find(|slot| slot.to_string().eq_ignore_ascii_case(s)).copied().ok_or(format!("unknown item slot {}", s));
+    }
+}
+
+impl ItemSlot {
+    // Necks and trinkets take no enchantments in game, rings only an enchanter's own and belts only engineering tinkers.
+    // The Idol slot stands for ranged weapons too, see RelicType.
+    pub fn can_enchant(&self, profession: Option<Profession>) -> bool {
+        match self {
+            ItemSlot::Neck | ItemSlot::Trinket1 | ItemSlot::Trinket2 => return false,
+            ItemSlot::Ring1 | ItemSlot::Ring2 => return profession == Some(Profession::Enchanting),
+            ItemSlot::Belt => return profession == Some(Profession::Engineering),
+            _ => return true,
+        }
+    }
+}
+
 const ITEM_SLOTS_ORDER: [ItemSlot; 17] = [ItemSlot::Head, ItemSlot::Neck, ItemSlot::Shoulder, ItemSlot::Back, ItemSlot::Chest, ItemSlot::Bracer, ItemSlot::WpnMain, ItemSlot::WpnOff, ItemSlot::Idol, ItemSlot::Gloves, ItemSlot::Belt, ItemSlot::Legs, ItemSlot::Feet, ItemSlot::Ring1, ItemSlot::Ring2, ItemSlot::Trinket1, ItemSlot::Trinket2];
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum Stat {
     Agility,
     AttackPower,
@@ -48,6 +85,22 @@ pub enum Stat {
     HitRate,
     Strength,
     Stamina,
+    Intellect,
+    Spirit,
+    SpellPower,
+    MP5,
+    Armor,
+    Defense,
+    Dodge,
+    Parry,
+    Block,
+    BlockValue,
+    Resilience,
+    SpellPenetration,
+}
+
+impl Stat {
+    pub const ALL: [Stat; 21] = [Stat::Agility, Stat::AttackPower, Stat::CritRate, Stat::APR, Stat::ExpertiseRate, Stat::HasteRate, Stat::HitRate, Stat::Strength, Stat::Stamina, Stat::Intellect, Stat::Spirit, Stat::SpellPower, Stat::MP5, Stat::Armor, Stat::Defense, Stat::Dodge, Stat::Parry, Stat::Block, Stat::BlockValue, Stat::Resilience, Stat::SpellPenetration];
 }
 
 impl std::fmt::Display for Stat {
@@ -56,7 +109,40 @@ impl std::fmt::Display for Stat {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Profession {
+    Alchemy,
+    Blacksmithing,
+    Enchanting,
+    Engineering,
+    Herbalism,
+    Inscription,
+    Jewelcrafting,
+    Leatherworking,
+    Mining,
+    Skinning,
+    Tailoring,
+}
+
+impl Profession {
+    pub const ALL: [Profession; 11] = [Profession::Alchemy, Profession::Blacksmithing, Profession::Enchanting, Profession::Engineering, Profession::Herbalism, Profession::Inscription, Profession::Jewelcrafting, Profession::Leatherworking, Profession::Mining, Profession::Skinning, Profession::Tailoring];
+    // Slots whose items get an extra prismatic socket, like a blacksmith's bracers and gloves. Gems and enchantments of a profession
+    // are marked in the catalog instead.
+    pub fn extra_socket_slots(&self) -> &'static [ItemSlot] {
+        match self {
+            Profession::Blacksmithing => return &[ItemSlot::Bracer, ItemSlot::Gloves],
+            _ => return &[],
+        }
+    }
+}
+
+impl std::fmt::Display for Profession {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return std::fmt::Debug::fmt(self, f);
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Bonus {
     stat: Stat,
     val: u32,
@@ -66,6 +152,7 @@ impl Bonus {
     pub fn new(stat: Stat, val: u32) -> Self {
         return Self{stat, val};
     }
+    // Bonus up for duration out of every cooldown. On-use effects over a fight are OnUse, procs depending on hits and haste are ProcBonus.
     pub fn new_proccable(stat: Stat, val: u32, duration: f64, cooldown: f64) -> Self {
         let true_val = val as f64 * duration / cooldown;
         return Self{stat, val: true_val.round() as u32};
@@ -77,7 +164,14 @@ impl Bonus {
     pub fn get_val(&self) -> u32 { return self.val; } 
 }
 
-#[derive(Clone)]
+impl std::fmt::Display for Bonus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(f, "+{} {}", self.val, self.stat);
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Bonuses {
     bonuses: Vec<Bonus>,
 }
@@ -96,6 +190,12 @@ impl Bonuses {
     }
 }
 
+impl std::fmt::Display for Bonuses {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(f, "{}", self.bonuses.iter().map(|bon| bon.to_string()).collect::<Vec<String>>().join(", "));
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -114,6 +214,12 @@ mod tests {
         assert_eq!(stats.get_stat_val(Stat::Agility), 17);
     }
     #[test]
+    fn enchantable_slots() {
+        assert!(!ItemSlot::Neck.can_enchant(None) && !ItemSlot::Trinket2.can_enchant(Some(Profession::Enchanting)));
+        assert!(!ItemSlot::Ring1.can_enchant(None) && ItemSlot::Ring1.can_enchant(Some(Profession::Enchanting)));
+        assert!(ItemSlot::Bracer.can_enchant(Some(Profession::Leatherworking)) && ItemSlot::Gloves.can_enchant(None));
+    }
+    #[test]
     fn bonus_procs() {
         assert_eq!(Bonus::new_proccable(Stat::AttackPower, 400, 15.0, 60.0).get_val(), 100);
     }
@@ -1,44 +1,219 @@
 use gemer::*;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-fn main() {
-    let mut sp = CurStats::new();
-    sp.set_stat(Stat::Strength, 113);
-    sp.set_stat(Stat::Agility, 2095);
-    sp.set_stat(Stat::APR, 1345);
-    sp.set_stat(Stat::AttackPower, 9944);
-    sp.set_stat(Stat::ExpertiseRate, 106);
-    sp.set_stat(Stat::CritRate, 1890);
-    sp.set_stat(Stat::HitRate, 201);
-    sp.set_stat(Stat::HasteRate, 340);
-    let mut my_build = ItemBuild::new();
-    let item = Item::new(String::from("neck"), ItemSlot::Neck, Bonuses::new(vec![]), vec![GemSocket::new(Color::Blue)], Some(Bonus::new(Stat::Agility, 4)), None);	
-    my_build.lock_item(item);
-    let item = Item::new(String::from("chest"), ItemSlot::Chest, Bonuses::new(vec![]), vec![GemSocket::new(Color::Yellow)], Some(Bonus::new(Stat::Agility, 6)), None);	
-    my_build.lock_item(item);
-    let item = Item::new(String::from("belt"), ItemSlot::Belt, Bonuses::new(vec![]), vec![GemSocket::new(Color::Red)], Some(Bonus::new(Stat::Agility, 6)), None);	
-    my_build.lock_item(item);
-    let item = Item::new(String::from("legs"), ItemSlot::Legs, Bonuses::new(vec![]), vec![GemSocket::new(Color::Yellow)], Some(Bonus::new(Stat::Agility, 6)), None);	
-    my_build.lock_item(item);
-    let item = Item::new(String::from("feet"), ItemSlot::Feet, Bonuses::new(vec![]), vec![GemSocket::new(Color::Yellow), GemSocket::new(Color::Yellow)], Some(Bonus::new(Stat::Agility, 6)), None);	
-    my_build.lock_item(item);
-    let item = Item::new(String::from("gloves"), ItemSlot::Gloves, Bonuses::new(vec![]), vec![GemSocket::new(Color::Yellow)], Some(Bonus::new(Stat::Agility, 6)), None);	
-    my_build.lock_item(item);
-    let mut reqs: Vec<Requirement> = vec![];
-    reqs.push(Requirement::RequirementCap(RequirementCap::new(Stat::APR, 1400, 100.0)));
-    reqs.push(Requirement::RequirementCap(RequirementCap::new(Stat::ExpertiseRate, 132, 2.19)));
-    reqs.push(Requirement::RequirementCap(RequirementCap::new(Stat::HitRate, 230, 2.19)));
-    reqs.push(Requirement::RequirementWeighted(RequirementWeighted::new(Stat::HasteRate, 1.5)));
-    reqs.push(Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.91)));
-    reqs.push(Requirement::RequirementWeighted(RequirementWeighted::new(Stat::CritRate, 1.42)));
-    let mut rotatable = Rotatables::new();
-    rotatable.rotate(Item::new(String::from("ring1_1"), ItemSlot::Ring1, Bonuses::new(vec![Bonus::new(Stat::Agility, 0)]), vec![GemSocket::new(Color::Yellow)], Some(Bonus::new(Stat::Agility, 6)), None));
-    rotatable.rotate(Item::new(String::from("ring1_2"), ItemSlot::Ring1, Bonuses::new(vec![Bonus::new(Stat::Agility, 100)]), vec![GemSocket::new(Color::Red)], Some(Bonus::new(Stat::Agility, 60)), None));
-    rotatable.rotate(Item::new(String::from("ring2_1"), ItemSlot::Ring2, Bonuses::new(vec![Bonus::new(Stat::Agility, 100)]), vec![GemSocket::new(Color::Yellow)], Some(Bonus::new(Stat::Agility, 6)), None));
-    rotatable.rotate(Item::new(String::from("ring2_2"), ItemSlot::Ring2, Bonuses::new(vec![Bonus::new(Stat::Agility, 0)]), vec![GemSocket::new(Color::Red)], Some(Bonus::new(Stat::Agility, 0)), None));
-    let mut sim = Simulator::new(sp, reqs, my_build, Some(rotatable));
-    let now = std::time::Instant::now();
-    sim.run(true, true, true, true, false);
-    let elapsed_time = now.elapsed();
-    println!("Running took {} seconds.", elapsed_time.as_millis() as f64 /1000.0);
-    println!("{}Total gain of this build is {}", sim.result(), sim.get_gain()); // 6665.45
+const EXIT_NO_SOLUTION: u8 = 1;
+const EXIT_INVALID_INPUT: u8 = 2;
+
+const USAGE: &str = "Usage:
+    gemer optimize <input-file> [options]
+    gemer score <input-file> [options]
+    gemer compare <input-file> [options]
+    gemer catalog list [--catalog <file>]...
+    gemer preset list
+
+compare optimizes the input without professions and then with each profession alone, reporting what each one adds.
+score evaluates the build as the input gives it, so it takes none of the --no-* options.
+
+Options:
+    --preset <name>        Start from this class preset's requirements, conversions and gems, overriding the input's preset
+    --catalog <file>       Use gems, enchants and consumables from this catalog instead of the built-in or preset one (repeatable)
+    --no-gems              Don't fill empty sockets
+    --no-enchants          Don't enchant items
+    --no-consumables       Don't pick flasks, elixirs, food or scrolls (--no-food does the same)
+    --no-prechant          Search gems before enchantments instead of after, which finds the same build at a different speed
+    --extra-socket <slot>  Add a prismatic socket (belt buckle, profession socket) to the item in this slot (repeatable)
+    --format <format>      text (default), json, csv (one row per socket, then the gain, stats and consumables) or input, which prints the solved build as an input file
+
+Exit codes: 0 on success (also when the output is closed early, like by head), 1 if no solution was found, 2 on invalid input or failing to write the output.";
+
+enum CliError {
+    Invalid(String),
+    Output(std::io::Error),
+}
+
+impl From<String> for CliError {
+    fn from(msg: String) -> Self {
+        return CliError::Invalid(msg);
+    }
+}
+
+impl From<std::io::Error> for CliError {
+    fn from(err: std::io::Error) -> Self {
+        return CliError::Output(err);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+    Input,
+}
+
+struct Options {
+    positional: Vec<String>,
+    catalogs: Vec<PathBuf>,
+    preset: Option<Preset>,
+    extra_sockets: Vec<ItemSlot>,
+    gems: bool,
+    enchants: bool,
+    consumables: bool,
+    prechant: bool,
+    format: OutputFormat,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut res = Self{positional: vec![], catalogs: vec![], preset: None, extra_sockets: vec![], gems: true, enchants: true, consumables: true, prechant: true, format: OutputFormat::Text};
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--catalog" => res.catalogs.push(PathBuf::from(args.next().ok_or("--catalog needs a file")?)),
+                "--preset" => res.preset = Some(Preset::try_from(args.next().ok_or("--preset needs a name")?.clone())?),
+                "--extra-socket" => res.extra_sockets.push(args.next().ok_or("--extra-socket needs a slot")?.parse::<ItemSlot>()?),
+                "--no-gems" => res.gems = false,
+                "--no-enchants" => res.enchants = false,
+                "--no-consumables" | "--no-food" => res.consumables = false,
+                "--no-prechant" => res.prechant = false,
+                "--format" => res.format = match args.next().map(|format| format.as_str()) {
+                    Some("text") => OutputFormat::Text,
+                    Some("json") => OutputFormat::Json,
+                    Some("csv") => OutputFormat::Csv,
+                    Some("input") => OutputFormat::Input,
+                    Some(other) => return Err(format!("unknown format {}", other)),
+                    None => return Err(String::from("--format needs a value")),
+                },
+                flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+                _ => res.positional.push(arg.clone()),
+            }
+        }
+        return Ok(res);
+    }
+    fn catalog(&self) -> Result<Catalog, String> {
+        if self.catalogs.is_empty() { return Ok(Catalog::default_catalog()); }
+        let mut res = Catalog::new();
+        for path in &self.catalogs {
+            res.merge(Catalog::from_file(path).map_err(|err| format!("{}: {}", path.display(), err))?);
+        }
+        return Ok(res);
+    }
+    fn input(&self) -> Result<OptimizationInput, String> {
+        if self.positional.len() != 1 { return Err(String::from("expected exactly one input file")); }
+        let path = Path::new(&self.positional[0]);
+        let mut res = OptimizationInput::from_file(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        if self.preset.is_some() { res.set_preset(self.preset.clone()); }
+        for slot in &self.extra_sockets {
+            if !res.set_extra_socket(*slot, true) { return Err(format!("no item in slot {} to add a socket to", slot)); }
+        }
+        return Ok(res);
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut out = std::io::stdout().lock();
+    let res = match args.first().map(|cmd| cmd.as_str()) {
+        Some("optimize") => Options::parse(&args[1..]).map_err(CliError::from).and_then(|opts| optimize(&opts, false, &mut out)),
+        Some("compare") => Options::parse(&args[1..]).map_err(CliError::from).and_then(|opts| compare(&opts, &mut out)),
+        Some("score") => Options::parse(&args[1..]).map_err(CliError::from).and_then(|opts| optimize(&opts, true, &mut out)),
+        Some("catalog") if args.get(1).map(|cmd| cmd.as_str()) == Some("list") => Options::parse(&args[2..]).map_err(CliError::from).and_then(|opts| list_catalog(&opts, &mut out)),
+        Some("preset") if args.get(1).map(|cmd| cmd.as_str()) == Some("list") => list_presets(&args[2..], &mut out),
+        Some("help") | Some("--help") | Some("-h") => writeln!(out, "{}", USAGE).map(|_| ExitCode::SUCCESS).map_err(CliError::from),
+        _ => Err(CliError::from(String::from(USAGE))),
+    };
+    match res.and_then(|code| out.flush().map(|_| code).map_err(CliError::from)) {
+        Ok(code) => code,
+        // Whoever reads the output, like head, stopped early and wants nothing more.
+        Err(CliError::Output(err)) if err.kind() == ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(CliError::Output(err)) => {
+            eprintln!("error: failed writing the output: {}", err);
+            ExitCode::from(EXIT_INVALID_INPUT)
+        },
+        Err(CliError::Invalid(msg)) => {
+            eprintln!("error: {}", msg);
+            ExitCode::from(EXIT_INVALID_INPUT)
+        },
+    }
+}
+
+fn optimize(opts: &Options, score_only: bool, out: &mut impl Write) -> Result<ExitCode, CliError> {
+    if score_only && !(opts.gems && opts.enchants && opts.consumables && opts.prechant) { return Err(CliError::from(String::from("score doesn't search, --no-gems, --no-enchants, --no-consumables and --no-prechant don't apply to it"))); }
+    let mut input = opts.input()?;
+    let mut sim = input.clone().into_simulator();
+    if !opts.catalogs.is_empty() { sim.set_bonus_finder(BestBonusFinder::from_catalog(&opts.catalog()?)); }
+    if score_only { sim.score(); } else { sim.run(opts.gems, opts.enchants, opts.consumables, opts.prechant); }
+    if !sim.has_solution() {
+        eprintln!("No solution was found.");
+        for (stat, missing) in sim.get_shortfall() {
+            let units = RatingTable::unit_of(*stat).map_or(String::new(), |unit| format!(" ({:.2}{})", input.get_ratings().to_units(*stat, missing.ceil() as u32).unwrap(), unit));
+            eprintln!("The closest build is {} {}{} short of the minimum.", missing, stat, units);
+        }
+        return Ok(ExitCode::from(EXIT_NO_SOLUTION));
+    }
+    match opts.format {
+        OutputFormat::Text => writeln!(out, "{}Total gain of this build is {}", sim.result(), sim.get_gain())?,
+        OutputFormat::Json => writeln!(out, "{}", sim.report().unwrap().to_json())?,
+        OutputFormat::Csv => write!(out, "{}", sim.report().unwrap().to_csv())?,
+        OutputFormat::Input => {
+            // Rotated items stay rotatable (with a single candidate each), so scoring the output counts their stats just like optimizing did.
+            let rotated_slots: Vec<ItemSlot> = input.get_rotatables().as_ref().map_or(vec![], |rotatables| rotatables.slots_in_rotation().to_vec());
+            let (rotated, locked): (Vec<Item>, Vec<Item>) = sim.get_solution_items().iter().cloned().partition(|item| rotated_slots.contains(&item.get_slot()));
+            input.set_build(ItemBuild::from(locked), if rotated.is_empty() { None } else { Some(Rotatables::from(rotated)) });
+            write!(out, "{}", input.to_toml_string().map_err(|err| err.to_string())?)?;
+        },
+    }
+    return Ok(ExitCode::SUCCESS);
+}
+
+fn compare(opts: &Options, out: &mut impl Write) -> Result<ExitCode, CliError> {
+    if opts.format != OutputFormat::Text { return Err(CliError::from(String::from("compare only prints text"))); }
+    let input = opts.input()?;
+    let catalog = if opts.catalogs.is_empty() { None } else { Some(opts.catalog()?) };
+    let (base, gains) = input.compare_professions(catalog.as_ref(), opts.gems, opts.enchants, opts.consumables, opts.prechant);
+    writeln!(out, "Gain without professions is {:.2}", base)?;
+    for (profession, gain) in gains {
+        writeln!(out, "\t{}: {:+.2}", profession, gain)?;
+    }
+    return Ok(ExitCode::SUCCESS);
+}
+
+fn list_catalog(opts: &Options, out: &mut impl Write) -> Result<ExitCode, CliError> {
+    if !opts.positional.is_empty() { return Err(CliError::from(format!("unexpected argument {}", opts.positional[0]))); }
+    let catalog = opts.catalog()?;
+    writeln!(out, "Gems:")?;
+    for gem in catalog.get_gems() {
+        writeln!(out, "\t{} [{}]: {}", gem.get_name(), gem.get_colors().iter().map(|color| color.to_string()).collect::<Vec<String>>().join("/"), gem.get_bonuses())?;
+    }
+    // Enchantments are stored per slot, so group them back by name.
+    let mut enchants: Vec<(&Enchantment, Vec<ItemSlot>)> = Vec::new();
+    for enchant in catalog.get_enchants() {
+        match enchants.iter_mut().find(|(known, _)| known.get_name() == enchant.get_name()) {
+            Some((_, slots)) => slots.push(enchant.get_slot()),
+            None => enchants.push((enchant, vec![enchant.get_slot()])),
+        }
+    }
+    writeln!(out, "Enchants:")?;
+    for (enchant, slots) in enchants {
+        let on_use = enchant.get_on_use().iter().map(|effect| format!(", on use {}", effect)).collect::<String>();
+        writeln!(out, "\t{} ({}): {}{}", enchant.get_name(), slots.iter().map(|slot| slot.to_string()).collect::<Vec<String>>().join(", "), enchant.get_bonuses(), on_use)?;
+    }
+    writeln!(out, "Consumables:")?;
+    for consumable in catalog.get_consumables() {
+        writeln!(out, "\t{} ({}): {}", consumable.get_name(), consumable.get_category(), consumable.get_bonuses())?;
+    }
+    return Ok(ExitCode::SUCCESS);
+}
+
+fn list_presets(args: &[String], out: &mut impl Write) -> Result<ExitCode, CliError> {
+    if let Some(arg) = args.first() { return Err(CliError::from(format!("unexpected argument {}", arg))); }
+    for preset in Preset::built_in() {
+        writeln!(out, "{} ({}, {}):", preset.get_name(), preset.get_description(), preset.get_relic())?;
+        for req in preset.get_requirements() {
+            writeln!(out, "\t{}", req)?;
+        }
+    }
+    return Ok(ExitCode::SUCCESS);
 }
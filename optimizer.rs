@@ -1,83 +1,165 @@
 use std::collections::HashMap;
-use crate::items::{Enchantment, Food};
-use crate::{Bonus, Bonuses, Color, CurStats, Gem, ItemSlot, Requirement, Stat, ITEM_SLOTS_ORDER};
+use crate::catalog::Catalog;
+use crate::items::{is_legal_loadout, Consumable, ConsumableCategory, Enchantment, Item};
+use crate::simulator::shares;
+use crate::{Bonus, CurStats, GainFunction, Gem, ItemSlot, Profession, Requirement};
 
 pub struct BestBonusFinder {
     gem_pool: Vec<Gem>,
+    all_enchants: Vec<Enchantment>,
+    // The enchantments of all_enchants the character's professions allow, by slot.
     enchant_pool: HashMap<ItemSlot, Vec<Enchantment>>,
-    food_pool: Vec<Food>,
+    consumable_pool: Vec<Consumable>,
+    professions: Vec<Profession>,
+}
+
+impl Default for BestBonusFinder {
+    fn default() -> Self {
+        return Self::new();
+    }
 }
 
 impl BestBonusFinder {
     pub fn new() -> Self {
-        let mut gem_pool: Vec<Gem> = Vec::new();
-        gem_pool.push(Gem::new(vec![Color::Red], Bonuses::new(vec![Bonus::new(Stat::APR, 20)]), String::from("fractured")));
-        gem_pool.push(Gem::new(vec![Color::Yellow], Bonuses::new(vec![Bonus::new(Stat::HasteRate, 20)]), String::from("quick")));
-        gem_pool.push(Gem::new(vec![Color::Red], Bonuses::new(vec![Bonus::new(Stat::Agility, 20)]), String::from("delicate")));
-        gem_pool.push(Gem::new(vec![Color::Yellow], Bonuses::new(vec![Bonus::new(Stat::HitRate, 20)]), String::from("rigid")));
-        gem_pool.push(Gem::new(vec![Color::Red], Bonuses::new(vec![Bonus::new(Stat::ExpertiseRate, 20)]), String::from("precise")));
-        gem_pool.push(Gem::new(vec![Color::Red, Color::Yellow], Bonuses::new(vec![Bonus::new(Stat::Agility, 10), Bonus::new(Stat::CritRate, 10)]), String::from("deadly")));
-        gem_pool.push(Gem::new(vec![Color::Red, Color::Yellow], Bonuses::new(vec![Bonus::new(Stat::ExpertiseRate, 10), Bonus::new(Stat::HitRate, 10)]), String::from("accurate")));
-        gem_pool.push(Gem::new(vec![Color::Red, Color::Yellow], Bonuses::new(vec![Bonus::new(Stat::Agility, 10), Bonus::new(Stat::HasteRate, 10)]), String::from("deft")));
-        gem_pool.push(Gem::new(vec![Color::Red, Color::Yellow], Bonuses::new(vec![Bonus::new(Stat::Agility, 10), Bonus::new(Stat::HitRate, 10)]), String::from("glinting")));
-        gem_pool.push(Gem::new(vec![Color::Red, Color::Blue], Bonuses::new(vec![Bonus::new(Stat::APR, 10)]), String::from("puissant")));
-        gem_pool.push(Gem::new(vec![Color::Red, Color::Blue, Color::Yellow], Bonuses::new(vec![Bonus::new(Stat::Agility, 10), Bonus::new(Stat::Strength, 10), Bonus::new(Stat::Stamina, 10)]), String::from("Nightmare's Tear")));
-        let mut enchant_pool = HashMap::new();
-        for item_slot in ITEM_SLOTS_ORDER {
-            enchant_pool.insert(item_slot, vec![Enchantment::new(item_slot, Bonuses::new(vec![Bonus::new(Stat::Agility, 20)]), "temp_agi".to_owned())]);
+        return Self::from_catalog(&Catalog::default_catalog());
+    }
+    pub fn from_catalog(catalog: &Catalog) -> Self {
+        let gem_pool = catalog.get_gems().to_vec();
+        let consumable_pool = catalog.get_consumables().to_vec();
+        let mut res = Self{gem_pool, all_enchants: catalog.get_enchants().to_vec(), enchant_pool: HashMap::new(), consumable_pool, professions: vec![]};
+        res.pool_enchants();
+        return res;
+    }
+    fn pool_enchants(&mut self) -> () {
+        self.enchant_pool.clear();
+        for enchant in &self.all_enchants {
+            if enchant.get_profession().is_some_and(|profession| !self.professions.contains(&profession)) { continue; }
+            self.enchant_pool.entry(enchant.get_slot()).or_default().push(enchant.clone());
         }
-        enchant_pool.entry(ItemSlot::Gloves).or_default().push(Enchantment::new(ItemSlot::Gloves, Bonuses::new(vec![Bonus::new(Stat::ExpertiseRate, 15)]), "expertise".to_owned()));
-        enchant_pool.entry(ItemSlot::Gloves).or_default().push(Enchantment::new(ItemSlot::Gloves, Bonuses::new(vec![Bonus::new(Stat::AttackPower, 44)]), "crusher".to_owned()));
-        enchant_pool.entry(ItemSlot::Gloves).or_default().push(Enchantment::new(ItemSlot::Gloves, Bonuses::new(vec![Bonus::new(Stat::HitRate, 20)]), "precision".to_owned()));
-        let mut food_pool = Vec::new();
-        food_pool.push(Food::new("crit loin".to_owned(), Bonuses::new(vec![Bonus::new(Stat::CritRate, 40)])));
-        food_pool.push(Food::new("agility loin".to_owned(), Bonuses::new(vec![Bonus::new(Stat::Agility, 40)])));
-        return Self{gem_pool, enchant_pool, food_pool};
+    }
+    pub fn set_professions(&mut self, professions: Vec<Profession>) -> () {
+        self.professions = professions;
+        self.pool_enchants();
+    }
+    pub fn get_professions(&self) -> &[Profession] {
+        return &self.professions;
+    }
+    // Enchantments with on-use effects count with their average over a fight this long.
+    pub fn set_fight_length(&mut self, fight_length: Option<f64>) -> () {
+        for enchant in &mut self.all_enchants {
+            enchant.average_on_use(fight_length);
+        }
+        self.pool_enchants();
+    }
+    // Whether the gem can go into one more socket of a build made of these items.
+    pub fn is_gem_allowed(&self, gem: &Gem, items: &[Item]) -> bool {
+        if let Some(profession) = gem.get_profession() {
+            if !self.professions.contains(&profession) { return false; }
+        }
+        return gem.is_within_limit(items);
+    }
+    pub(crate) fn get_gems(&self) -> &[Gem] {
+        return &self.gem_pool;
+    }
+    pub(crate) fn get_enchants_for_slot(&self, slot: ItemSlot) -> &[Enchantment] {
+        return self.enchant_pool.get(&slot).map_or(&[], |enchants| enchants.as_slice());
     }
     fn get_gain_by_bonus(&self, bonus: &Bonus, reference: &CurStats, reqs: &[Requirement]) -> f64 {
         let mut gain: f64 = 0.0;
         for req in reqs {
-            gain += match req {
-                Requirement::RequirementCap(ref cap) if cap.get_stat() == bonus.get_stat() => cap.calculate_gain_incremental(bonus.get_val(), reference.get_stat_val(bonus.get_stat())),
-                Requirement::RequirementWeighted(ref weighted) if weighted.get_stat() == bonus.get_stat() => weighted.calculate_gain(bonus.get_val()),
-                _ => 0.0,
-            }
+            gain += req.gain_by_bonus(bonus, reference);
         }
         return gain;
     }
-    pub fn get_best_gem(&self, reference: &CurStats, reqs: &[Requirement], allow_tear: bool) -> Gem {
-        let mut temp_gems = self.gem_pool.clone();
-        temp_gems.sort_by_key(|gem| {
-            let mut gain: f64 = 0.0;
-            for bonus in gem.get_bonuses().iter() {
-                gain += self.get_gain_by_bonus(bonus, reference, reqs);
-            }
-            return unsafe { gain.round().to_int_unchecked::<u32>() };
-        });
-        temp_gems.reverse();
-        if !allow_tear && temp_gems[0].get_name() == "Nightmare's Tear" { return temp_gems[1].clone(); }
-        return temp_gems[0].clone();
-    }
-    pub fn get_best_enchantment_by_slot(&self, slot: ItemSlot, reference: &CurStats, reqs: &[Requirement]) -> Enchantment {
-        let mut temp_enchants = self.enchant_pool.get(&slot).unwrap().clone();
-        temp_enchants.sort_by_key(|enchant| {
-            let mut gain: f64 = 0.0;
-            for bonus in enchant.get_bonuses().iter() {
-                gain += self.get_gain_by_bonus(bonus, reference, reqs);
-            }
-            return unsafe { gain.round().to_int_unchecked::<u32>() };
-        });
-        temp_enchants.reverse();
-        return temp_enchants[0].clone();
-    }
-    pub fn get_useful_food(&self, reqs: &[Requirement]) -> Vec<Food> {
+    // Consumables with any gain or helping to a minimum, leaving out those another one of the same category beats or equals in every requirement.
+    pub fn get_useful_consumables(&self, reqs: &[Requirement]) -> Vec<Consumable> {
         let cur_stats_clear = CurStats::new();
-        self.food_pool.iter().filter(|&food| {
+        let useful: Vec<&Consumable> = self.consumable_pool.iter().filter(|&consumable| {
             let mut gain: f64 = 0.0;
-            for bonus in food.get_bonuses().iter() {
+            for bonus in consumable.get_bonuses().iter() {
                 gain += self.get_gain_by_bonus(bonus, &cur_stats_clear, reqs);
             }
-            return gain > 0.0;
-        }).cloned().collect::<Vec<Food>>()
+            return gain > 0.0 || reqs.iter().any(|req| matches!(req, Requirement::RequirementMinimum(_)) && consumable.get_bonuses().iter().any(|bonus| req.contribution(bonus.get_stat()) > 0.0));
+        }).collect();
+        let shares: Vec<Vec<f64>> = useful.iter().map(|consumable| shares(reqs, consumable.get_bonuses())).collect();
+        return useful.iter().enumerate().filter(|(ind, consumable)| !useful.iter().enumerate().any(|(other, better)| {
+            other != *ind && better.get_category() == consumable.get_category() && shares[other].iter().zip(&shares[*ind]).all(|(a, b)| a >= b)
+                && (other < *ind || shares[other].iter().zip(&shares[*ind]).any(|(a, b)| a > b))
+        })).map(|(_, consumable)| (*consumable).clone()).collect();
+    }
+    // Every legal loadout of useful consumables that has no room for one more, most promising first.
+    // Consumables only add stats, so a loadout with room left is never better than the same one filled up.
+    pub fn get_consumable_loadouts(&self, reqs: &[Requirement]) -> Vec<Vec<Consumable>> {
+        let useful = self.get_useful_consumables(reqs);
+        let mut loadouts: Vec<Vec<Consumable>> = vec![vec![]];
+        for category in ConsumableCategory::ALL {
+            let options: Vec<&Consumable> = useful.iter().filter(|consumable| consumable.get_category() == category).collect();
+            if options.is_empty() { continue; }
+            let mut next = Vec::new();
+            for loadout in loadouts {
+                for option in &options {
+                    let mut with = loadout.clone();
+                    with.push((*option).clone());
+                    if is_legal_loadout(&with) { next.push(with); }
+                }
+                next.push(loadout);
+            }
+            loadouts = next;
+        }
+        let is_full = |loadout: &Vec<Consumable>| useful.iter().all(|consumable| loadout.iter().any(|taken| taken.get_category().excludes(consumable.get_category())));
+        let cur_stats_clear = CurStats::new();
+        let mut res: Vec<(f64, Vec<Consumable>)> = loadouts.into_iter().filter(is_full).map(|loadout| {
+            let gain = loadout.iter().flat_map(|consumable| consumable.get_bonuses().iter()).map(|bonus| self.get_gain_by_bonus(bonus, &cur_stats_clear, reqs)).sum();
+            return (gain, loadout);
+        }).collect();
+        res.sort_by(|a, b| b.0.total_cmp(&a.0));
+        return res.into_iter().map(|(_, loadout)| loadout).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use crate::ratings::RatingTable;
+    use crate::{RequirementDiminishing, RequirementWeighted, Stat};
+
+    #[test]
+    fn professions_unlock_enchants() {
+        let mut finder = BestBonusFinder::new();
+        let names = |finder: &BestBonusFinder, slot: ItemSlot| finder.get_enchants_for_slot(slot).iter().map(|enchant| enchant.get_name().to_owned()).collect::<Vec<String>>();
+        assert!(names(&finder, ItemSlot::Neck).is_empty() && names(&finder, ItemSlot::Ring1).is_empty());
+        assert!(!names(&finder, ItemSlot::Bracer).contains(&String::from("fur lining attack power")));
+        finder.set_professions(vec![Profession::Enchanting, Profession::Leatherworking]);
+        assert!(names(&finder, ItemSlot::Ring1).contains(&String::from("ring assault")));
+        assert!(names(&finder, ItemSlot::Bracer).contains(&String::from("fur lining attack power")));
+    }
+
+    #[test]
+    fn diminishing_gem_exact() {
+        let finder = BestBonusFinder::new();
+        let mut dodge = RequirementDiminishing::new(Stat::Dodge, 0.972, 116.890707, 1.0).unwrap();
+        let mut base = CurStats::new();
+        base.set_stat(Stat::Dodge, 400);
+        dodge.make_incremental(&base);
+        let reqs = vec![Requirement::RequirementDiminishing(dodge)];
+        let mut reference = CurStats::new();
+        reference.set_stat(Stat::Dodge, 200);
+        let per_percent = RatingTable::default().rating_per_unit(Stat::Dodge).unwrap();
+        let after = |rating: f64| 1.0 / (1.0 / 116.890707 + 0.972 / (rating / per_percent)) * per_percent;
+        let gain = finder.get_gain_by_bonus(&Bonus::new(Stat::Dodge, 20), &reference, &reqs);
+        assert!((gain - (after(620.0) - after(600.0))).abs() < 1e-9);
+        let mut with_gem = reference.clone();
+        with_gem.add_stat(Stat::Dodge, 20);
+        assert!((with_gem.calculate_gain(&reqs) - reference.calculate_gain(&reqs) - gain).abs() < 1e-9);
+        assert!(gain < 20.0 / 0.972);
+    }
+    #[test]
+    fn flask_or_elixirs() {
+        let src = "[[consumable]]\nname = \"flask\"\ncategory = \"Flask\"\nbonuses = [{ stat = \"SpellPower\", val = 125 }]\n\n[[consumable]]\nname = \"weak flask\"\ncategory = \"Flask\"\nbonuses = [{ stat = \"SpellPower\", val = 100 }]\n\n[[consumable]]\nname = \"battle\"\ncategory = \"BattleElixir\"\nbonuses = [{ stat = \"SpellPower\", val = 58 }]\n\n[[consumable]]\nname = \"guardian\"\ncategory = \"GuardianElixir\"\nbonuses = [{ stat = \"Intellect\", val = 45 }]\n\n[[food]]\nname = \"fish\"\nbonuses = [{ stat = \"SpellPower\", val = 46 }]\n";
+        let finder = BestBonusFinder::from_catalog(&Catalog::from_str(src).unwrap());
+        let reqs = vec![Requirement::RequirementWeighted(RequirementWeighted::new(Stat::SpellPower, 1.0)), Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Intellect, 1.0))];
+        let names: Vec<Vec<String>> = finder.get_consumable_loadouts(&reqs).iter().map(|loadout| loadout.iter().map(|consumable| consumable.get_name().to_owned()).collect()).collect();
+        assert_eq!(names, vec![vec!["flask", "fish"], vec!["battle", "guardian", "fish"]]);
     }
 }
\ No newline at end of file
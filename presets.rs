@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+use crate::catalog::Catalog;
+use crate::conversions::StatConversions;
+use crate::simulator::Requirement;
+use crate::ItemSlot;
+
+const PRESETS: &str = include_str!("data/presets.toml");
+
+// What a class wears in the Idol slot. Only ranged weapons take enchantments there.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum RelicType {
+    Idol,
+    Libram,
+    Totem,
+    Sigil,
+    Ranged,
+}
+
+impl std::fmt::Display for RelicType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return std::fmt::Debug::fmt(self, f);
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PresetEntry {
+    name: String,
+    description: String,
+    relic: RelicType,
+    gems: Vec<String>,
+    enchants: Vec<String>,
+    requirements: Vec<Requirement>,
+    #[serde(default)]
+    conversions: StatConversions,
+}
+
+#[derive(Deserialize)]
+struct PresetsFile {
+    preset: Vec<PresetEntry>,
+}
+
+// Requirements, conversions and catalog entries of a class and spec. Input files refer to presets by name.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Preset {
+    entry: PresetEntry,
+}
+
+impl Preset {
+    pub fn built_in() -> Vec<Preset> {
+        let file: PresetsFile = toml::from_str(PRESETS).expect("Built-in presets are malformed!");
+        return file.preset.into_iter().map(|entry| Self{entry}).collect();
+    }
+    pub fn by_name(name: &str) -> Option<Preset> {
+        return Self::built_in().into_iter().find(|preset| preset.get_name().eq_ignore_ascii_case(name));
+    }
+    pub fn get_name(&self) -> &str { return &self.entry.name; }
+    pub fn get_description(&self) -> &str { return &self.entry.description; }
+    pub fn get_relic(&self) -> RelicType { return self.entry.relic; }
+    pub fn get_requirements(&self) -> &[Requirement] { return &self.entry.requirements; }
+    pub fn get_conversions(&self) -> &StatConversions { return &self.entry.conversions; }
+    // The preset's requirements with those on the same stat replaced by overrides, and overrides on other stats added.
+    pub fn merge_requirements(&self, overrides: Vec<Requirement>) -> Vec<Requirement> {
+        let mut res: Vec<Requirement> = self.entry.requirements.iter().filter(|req| !overrides.iter().any(|other| other.get_stat().is_some() && other.get_stat() == req.get_stat())).cloned().collect();
+        res.extend(overrides);
+        return res;
+    }
+    // The gems and enchantments of base the preset names, consumables are left as they are.
+    pub fn catalog(&self, base: &Catalog) -> Catalog {
+        let mut res = base.clone();
+        res.retain_gems(|gem| self.entry.gems.iter().any(|name| name == gem.get_name()));
+        res.retain_enchants(|enchant| self.entry.enchants.iter().any(|name| name == enchant.get_name()) && (enchant.get_slot() != ItemSlot::Idol || self.entry.relic == RelicType::Ranged));
+        return res;
+    }
+}
+
+impl TryFrom<String> for Preset {
+    type Error = String;
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        return Self::by_name(&name).ok_or(format!("unknown preset {}", name));
+    }
+}
+
+impl From<Preset> for String {
+    fn from(preset: Preset) -> Self {
+        return preset.entry.name;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Stat;
+
+    #[test]
+    fn presets_name_catalog_entries() {
+        let catalog = Catalog::default_catalog();
+        for preset in Preset::built_in() {
+            for name in &preset.entry.gems {
+                assert!(catalog.get_gems().iter().any(|gem| gem.get_name() == name), "{} names unknown gem {}", preset.get_name(), name);
+            }
+            for name in &preset.entry.enchants {
+                assert!(catalog.get_enchants().iter().any(|enchant| enchant.get_name() == name), "{} names unknown enchant {}", preset.get_name(), name);
+            }
+        }
+    }
+    #[test]
+    fn overrides_per_stat() {
+        let preset = Preset::by_name("Feral-Cat").unwrap();
+        let reqs = preset.merge_requirements(vec![Requirement::RequirementWeighted(crate::RequirementWeighted::new(Stat::HitRate, 2.0))]);
+        assert_eq!(reqs.len(), preset.get_requirements().len());
+        assert!(matches!(reqs.last(), Some(Requirement::RequirementWeighted(_))) && reqs.last().unwrap().get_stat() == Some(Stat::HitRate));
+        assert!(preset.catalog(&Catalog::default_catalog()).get_enchants().iter().all(|enchant| enchant.get_slot() != ItemSlot::Idol));
+    }
+}
@@ -0,0 +1,141 @@
+use serde::{Deserialize, Serialize};
+use crate::ratings::RatingTable;
+use crate::{Bonus, Stat};
+
+// What makes a proc happen. Speed is the seconds between events that can trigger it before haste, like weapon speed or cast time.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProcTrigger {
+    Chance { chance: f64, speed: f64 },
+    Ppm { ppm: f64 },
+}
+
+// A stat bonus lasting duration seconds whenever it procs, at most once per cooldown (internal cooldown, 0 if there's none).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ProcBonus {
+    stat: Stat,
+    val: u32,
+    duration: f64,
+    #[serde(default)]
+    cooldown: f64,
+    trigger: ProcTrigger,
+}
+
+impl ProcBonus {
+    pub fn new(stat: Stat, val: u32, duration: f64, cooldown: f64, trigger: ProcTrigger) -> Self {
+        return Self{stat, val, duration, cooldown, trigger};
+    }
+    pub fn get_stat(&self) -> Stat { return self.stat; }
+    pub fn get_val(&self) -> u32 { return self.val; }
+    pub fn get_duration(&self) -> f64 { return self.duration; }
+    pub fn get_cooldown(&self) -> f64 { return self.cooldown; }
+    pub fn get_trigger(&self) -> ProcTrigger { return self.trigger; }
+    // Procs per second off cooldown, haste being the multiplier on attack or cast speed.
+    fn rate(&self, haste: f64) -> f64 {
+        match self.trigger {
+            ProcTrigger::Chance{chance, speed} => return chance * haste / speed,
+            ProcTrigger::Ppm{ppm} => return ppm * haste / 60.0,
+        }
+    }
+    // Share of the time the bonus is up. After a proc the next one comes a cooldown plus a random wait later,
+    // and one coming before the bonus runs out refreshes it.
+    pub fn uptime(&self, haste: f64) -> f64 {
+        let rate = self.rate(haste);
+        if rate <= 0.0 || self.duration <= 0.0 { return 0.0; }
+        let blocked = self.cooldown.min(self.duration);
+        return (blocked + (1.0 - (-rate * (self.duration - blocked)).exp()) / rate) / (self.cooldown + 1.0 / rate);
+    }
+    // Average stat from the proc with haste_rating, and how much each further point of haste rating adds to it.
+    pub fn average(&self, haste_rating: f64, ratings: &RatingTable) -> (f64, f64) {
+        let per_point = 1.0 / (ratings.rating_per_unit(Stat::HasteRate).unwrap() * 100.0);
+        let haste = 1.0 + haste_rating * per_point;
+        let step = 1e-4;
+        let slope = (self.uptime(haste + step) - self.uptime(haste - step)) / (2.0 * step) * per_point;
+        return (self.val as f64 * self.uptime(haste), self.val as f64 * slope);
+    }
+}
+
+// A bonus the character uses every cooldown for duration seconds. When held for another cooldown of aligned_with seconds, like
+// a bloodlust or a trinket of the same kind, it's only used when that one is ready too.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OnUse {
+    stat: Stat,
+    val: u32,
+    duration: f64,
+    cooldown: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aligned_with: Option<f64>,
+}
+
+impl OnUse {
+    pub fn new(stat: Stat, val: u32, duration: f64, cooldown: f64) -> Self {
+        return Self{stat, val, duration, cooldown, aligned_with: None};
+    }
+    pub fn get_stat(&self) -> Stat { return self.stat; }
+    pub fn get_val(&self) -> u32 { return self.val; }
+    pub fn get_aligned_with(&self) -> Option<f64> { return self.aligned_with; }
+    pub fn set_aligned_with(&mut self, aligned_with: Option<f64>) -> () {
+        self.aligned_with = aligned_with;
+    }
+    // Seconds between uses, a use can't come before the last one ran out.
+    fn effective_cooldown(&self) -> f64 {
+        let cooldown = self.cooldown.max(self.duration);
+        return match self.aligned_with {
+            Some(other) if other > 0.0 => (cooldown / other).ceil().max(1.0) * other,
+            _ => cooldown,
+        };
+    }
+    // Share of the fight the bonus is up, used on pull and then on cooldown. Without a fight length the fight never ends.
+    pub fn uptime(&self, fight_length: Option<f64>) -> f64 {
+        let cooldown = self.effective_cooldown();
+        if cooldown <= 0.0 { return 0.0; }
+        let fight = match fight_length {
+            Some(fight) if fight > 0.0 => fight,
+            _ => return self.duration / cooldown,
+        };
+        let mut up = 0.0;
+        let mut start = 0.0;
+        while start < fight {
+            up += self.duration.min(fight - start);
+            start += cooldown;
+        }
+        return up / fight;
+    }
+    pub fn to_bonus(&self, fight_length: Option<f64>) -> Bonus {
+        return Bonus::new(self.stat, (self.val as f64 * self.uptime(fight_length)).round() as u32);
+    }
+}
+
+impl std::fmt::Display for OnUse {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(f, "+{} {} for {}s every {}s", self.val, self.stat, self.duration, self.cooldown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cooldown_and_refresh() {
+        // A 10% chance per second with a 45 second cooldown is up 10 seconds out of 55 on average.
+        let trinket = ProcBonus::new(Stat::AttackPower, 1000, 10.0, 45.0, ProcTrigger::Chance{chance: 0.1, speed: 1.0});
+        assert!((trinket.uptime(1.0) - 10.0 / 55.0).abs() < 1e-9);
+        // Without a cooldown procs refresh the bonus, so it's up unless there was none for 15 seconds.
+        let berserking = ProcBonus::new(Stat::AttackPower, 400, 15.0, 0.0, ProcTrigger::Ppm{ppm: 1.0});
+        assert!((berserking.uptime(1.0) - (1.0 - (-0.25_f64).exp())).abs() < 1e-9);
+        let (average, slope) = berserking.average(0.0, &RatingTable::default());
+        assert!(average > 88.0 && average < 89.0 && slope > 0.0);
+    }
+    #[test]
+    fn uses_in_fight() {
+        let mut accelerators = OnUse::new(Stat::HasteRate, 340, 12.0, 60.0);
+        assert_eq!(accelerators.to_bonus(None).get_val(), 68);
+        assert_eq!(accelerators.to_bonus(Some(300.0)).get_val(), 68);
+        // Used at 0, 60 and 120 seconds of a 150 second fight.
+        assert_eq!(accelerators.to_bonus(Some(150.0)).get_val(), 82);
+        // Held for a 3 minute cooldown it's used only at 0 and 180.
+        accelerators.set_aligned_with(Some(180.0));
+        assert_eq!(accelerators.to_bonus(Some(300.0)).get_val(), 27);
+    }
+}
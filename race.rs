@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+use crate::char::CurStats;
+use crate::conversions::StatConversions;
+use crate::ratings::RatingTable;
+use crate::Stat;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Race {
+    Human,
+    Dwarf,
+    NightElf,
+    Gnome,
+    Draenei,
+    Orc,
+    Undead,
+    Tauren,
+    Troll,
+    BloodElf,
+}
+
+// Main hand weapon type, only for racial expertise.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum WeaponType {
+    Axe,
+    Dagger,
+    Fist,
+    Mace,
+    Polearm,
+    Staff,
+    Sword,
+}
+
+impl std::fmt::Display for Race {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return std::fmt::Debug::fmt(self, f);
+    }
+}
+
+impl Race {
+    // Percent (skill for expertise) the race already has towards a cap on stat, so the cap needs that much less rating.
+    pub fn cap_bonus(&self, stat: Stat, weapon: Option<WeaponType>) -> f64 {
+        match (self, stat, weapon) {
+            (Race::Draenei, Stat::HitRate, _) => 1.0,
+            (Race::Human, Stat::ExpertiseRate, Some(WeaponType::Sword | WeaponType::Mace)) => 3.0,
+            (Race::Dwarf, Stat::ExpertiseRate, Some(WeaponType::Mace)) => 5.0,
+            (Race::Orc, Stat::ExpertiseRate, Some(WeaponType::Axe)) => 5.0,
+            _ => 0.0,
+        }
+    }
+    // Percent the race has on stat that doesn't lower a cap, like a night elf's dodge.
+    pub fn base_bonus(&self, stat: Stat) -> f64 {
+        match (self, stat) {
+            (Race::NightElf, Stat::Dodge) => 2.0,
+            _ => 0.0,
+        }
+    }
+    // Racial percents that don't lower a cap count as the rating they're worth.
+    pub fn apply_base_stats(&self, stats: &mut CurStats, ratings: &RatingTable) -> () {
+        let units = self.base_bonus(Stat::Dodge);
+        if units > 0.0 { stats.add_stat(Stat::Dodge, ratings.to_rating(Stat::Dodge, units).unwrap()); }
+    }
+    // Tauren get 5% more health, which is counted as stamina.
+    pub fn apply_multipliers(&self, conversions: &mut StatConversions) -> () {
+        match self {
+            Race::Human => conversions.multiply(Stat::Spirit, 1.03),
+            Race::Gnome => conversions.multiply(Stat::Intellect, 1.05),
+            Race::Tauren => conversions.multiply(Stat::Stamina, 1.05),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weapon_expertise() {
+        assert_eq!(Race::Human.cap_bonus(Stat::ExpertiseRate, Some(WeaponType::Mace)), 3.0);
+        assert_eq!(Race::Human.cap_bonus(Stat::ExpertiseRate, Some(WeaponType::Axe)), 0.0);
+        assert_eq!(Race::Orc.cap_bonus(Stat::ExpertiseRate, None), 0.0);
+        assert_eq!(Race::Draenei.cap_bonus(Stat::HitRate, None), 1.0);
+    }
+}
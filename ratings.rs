@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use crate::Stat;
+
+const MAX_LEVEL: u32 = 80;
+
+// What a combat rating converts to: expertise and defense ratings give skill points, the others give percents.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RatingUnit {
+    Percent,
+    Skill,
+}
+
+impl std::fmt::Display for RatingUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RatingUnit::Percent => write!(f, "%"),
+            RatingUnit::Skill => write!(f, " skill"),
+        }
+    }
+}
+
+// Combat rating conversions at one character level. Written to and read from input files as just the level.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub struct RatingTable {
+    level: u32,
+}
+
+impl RatingTable {
+    pub fn for_level(level: u32) -> Option<Self> {
+        if level == 0 || level > MAX_LEVEL { return None; }
+        return Some(Self{level});
+    }
+    pub fn get_level(&self) -> u32 { return self.level; }
+    pub fn unit_of(stat: Stat) -> Option<RatingUnit> {
+        match stat {
+            Stat::HitRate | Stat::CritRate | Stat::HasteRate | Stat::APR | Stat::Dodge | Stat::Parry | Stat::Block | Stat::Resilience => Some(RatingUnit::Percent),
+            Stat::ExpertiseRate | Stat::Defense => Some(RatingUnit::Skill),
+            _ => None,
+        }
+    }
+    // Rating needed for one percent, or one skill point, at this level. None for stats that aren't ratings.
+    // Hit is melee hit, spells need less rating per percent.
+    pub fn rating_per_unit(&self, stat: Stat) -> Option<f64> {
+        let at_60 = match stat {
+            Stat::HitRate | Stat::HasteRate => 10.0,
+            Stat::CritRate => 14.0,
+            Stat::ExpertiseRate => 2.5,
+            Stat::APR => 64.0 / 15.0,
+            Stat::Defense => 1.5,
+            // 45.25 rating per percent at 80, dearer than in TBC.
+            Stat::Dodge => 13.8,
+            Stat::Parry => 15.0,
+            Stat::Block => 5.0,
+            Stat::Resilience => 28.75,
+            _ => return None,
+        };
+        return Some(at_60 * self.level_factor());
+    }
+    // Smallest rating worth at least units, so a cap converted with it is really reached.
+    pub fn to_rating(&self, stat: Stat, units: f64) -> Option<u32> {
+        return self.rating_per_unit(stat).map(|per_unit| (units * per_unit - 1e-9).ceil().max(0.0) as u32);
+    }
+    pub fn to_units(&self, stat: Stat, rating: u32) -> Option<f64> {
+        return self.rating_per_unit(stat).map(|per_unit| rating as f64 / per_unit);
+    }
+    // How much more rating the game asks for than at level 60.
+    fn level_factor(&self) -> f64 {
+        let level = self.level as f64;
+        if self.level < 10 { return 2.0 / 52.0; }
+        if self.level <= 60 { return (level - 8.0) / 52.0; }
+        if self.level <= 70 { return 82.0 / (262.0 - 3.0 * level); }
+        return 82.0 / 52.0 * (131.0_f64 / 63.0).powf((level - 70.0) / 10.0);
+    }
+}
+
+impl Default for RatingTable {
+    fn default() -> Self {
+        return Self{level: MAX_LEVEL};
+    }
+}
+
+impl TryFrom<u32> for RatingTable {
+    type Error = String;
+    fn try_from(level: u32) -> Result<Self, Self::Error> {
+        return Self::for_level(level).ok_or(format!("no rating conversions for level {}, only for 1 to {}", level, MAX_LEVEL));
+    }
+}
+
+impl From<RatingTable> for u32 {
+    fn from(table: RatingTable) -> Self {
+        return table.level;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps_at_80() {
+        let table = RatingTable::default();
+        assert_eq!(table.to_rating(Stat::HitRate, 8.0), Some(263));
+        assert_eq!(table.to_rating(Stat::ExpertiseRate, 26.0), Some(214));
+        assert!((table.rating_per_unit(Stat::Dodge).unwrap() - 45.25).abs() < 0.01);
+        assert_eq!(table.to_rating(Stat::Agility, 1.0), None);
+    }
+    #[test]
+    fn levels_join() {
+        let at_70 = RatingTable::for_level(70).unwrap().rating_per_unit(Stat::HitRate).unwrap();
+        assert!((at_70 - 15.77).abs() < 0.01);
+        assert_eq!(RatingTable::for_level(60).unwrap().rating_per_unit(Stat::CritRate), Some(14.0));
+        assert!(RatingTable::for_level(81).is_none());
+    }
+}
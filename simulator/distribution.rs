@@ -1,39 +1,45 @@
 use crate::char::CurStats;
-use crate::items::{Item, Food};
+use crate::items::{Consumable, Item};
+use crate::ratings::RatingTable;
 use crate::simulator::requirements::Requirement;
 
 pub struct Distribution {
     stat_growth: CurStats,
     gain: f64,
     items: Vec<Item>,
-    food: Option<Food>,
+    consumables: Vec<Consumable>,
 }
 
 impl Distribution {
-    pub fn new(stat_growth: CurStats, reqs: &[Requirement], item_refs: &[Item]) -> Self {
+    pub fn new(mut stat_growth: CurStats, reqs: &[Requirement], item_refs: &[Item]) -> Self {
         let mut items: Vec<Item> = Vec::new();
         for item in item_refs {
             items.push(item.clone());
         }
+        apply_active_metas(&mut stat_growth, &items);
         let gain = stat_growth.calculate_gain(reqs);
-        return Self{stat_growth, gain, items, food: None};
+        return Self{stat_growth, gain, items, consumables: vec![]};
     }
     pub fn is_new_better(&self, other: &Option<&Distribution>) -> bool {
         if other.is_none() { return false; }
         return self.gain < other.unwrap().gain;
     }
-    pub fn to_string(&self) -> String {
+    pub fn to_string(&self, ratings: &RatingTable) -> String {
         let mut res = String::with_capacity(120);
-        if self.food.is_some() {
-            res += &format!("Eat {} for this gain in stats:\n", self.food.as_ref().unwrap().get_name());
+        if !self.consumables.is_empty() {
+            res += &format!("Use {} for this gain in stats:\n", self.consumables.iter().map(|consumable| format!("{} ({})", consumable.get_name(), consumable.get_category())).collect::<Vec<String>>().join(", "));
         }
         for item in &self.items {
             res += &format!("[{}]{} <- {}:\n", &item.get_slot().to_string(), &item.get_name(), if item.get_enchantment().is_some() { &item.get_enchantment().as_ref().unwrap().get_name() } else { "None" });
             for socket in item.get_sockets() {
-                res += &format!("\t{} <- {} ({})\n", &socket.get_color().to_string(), if socket.get_gem().is_some() { socket.get_gem().as_ref().unwrap().get_name() } else { "None" }, if socket.does_match() {"match"} else {"mismatch"});
+                let inactive = socket.get_gem().as_ref().is_some_and(|gem| gem.is_meta() && !gem.is_active(&self.items));
+                res += &format!("\t{} <- {} ({}{})\n", &socket.get_color().to_string(), if socket.get_gem().is_some() { socket.get_gem().as_ref().unwrap().get_name() } else { "None" }, if socket.does_match() {"match"} else {"mismatch"}, if inactive {", inactive"} else {""});
+            }
+            if let Some(bonus) = item.get_socket_bonus() {
+                res += &format!("\tSocket bonus {}: {}\n", bonus, if item.is_socket_bonus_active() {"taken"} else {"skipped"});
             }
         }
-        return self.stat_growth.to_string() + &res;
+        return self.stat_growth.to_string_with_ratings(ratings) + &res;
     }
     pub fn get_gain(&self) -> f64 {
         return self.gain;
@@ -44,7 +50,19 @@ impl Distribution {
     pub fn get_items(&self) -> &[Item] {
         return &self.items;
     }
-    pub fn set_food(&mut self, f: Food) -> () {
-        self.food = Some(f);
+    pub fn get_consumables(&self) -> &[Consumable] {
+        return &self.consumables;
+    }
+    pub fn set_consumables(&mut self, consumables: Vec<Consumable>) -> () {
+        self.consumables = consumables;
+    }
+}
+
+// Meta gems are left out of stat_growth by the search since their activation depends on the whole build.
+pub(crate) fn apply_active_metas(stat_growth: &mut CurStats, items: &[Item]) -> () {
+    for socket in items.iter().flat_map(|item| item.get_sockets().iter()) {
+        if let Some(gem) = socket.get_gem() {
+            if gem.is_meta() && gem.is_active(items) { gem.get_bonuses().apply_bonuses(stat_growth); }
+        }
     }
 }
\ No newline at end of file
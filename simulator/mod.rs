@@ -1,29 +1,136 @@
 mod requirements;
 mod distribution;
+mod result;
+mod solver;
 
 use crate::optimizer::BestBonusFinder;
-pub use crate::simulator::requirements::{Requirement, RequirementCap, RequirementWeighted};
+pub use crate::simulator::requirements::{BreakpointThreshold, GainFunction, PiecewiseRegion, Requirement, RequirementBreakpoint, RequirementCustom, RequirementCap, RequirementDiminishing, RequirementMinimum, RequirementPiecewise, RequirementWeighted};
+pub(crate) use crate::simulator::requirements::shares;
+pub use crate::simulator::result::{ConsumableResult, ItemResult, OptimizationResult, SocketResult};
 use crate::simulator::distribution::Distribution;
+use crate::simulator::solver::Solver;
 use crate::char::{CurStats, ItemBuild, Rotatables, RotateVariantsGenerator};
-use crate::items::Item;
-use std::thread;
-use std::sync::Arc;
+use crate::items::{Item, ItemSet};
+use crate::conversions::StatConversions;
+use crate::ratings::RatingTable;
+use crate::race::{Race, WeaponType};
+use crate::procs::ProcBonus;
+use crate::{Profession, Stat};
 
 pub struct Simulator {
+    cur_stats: CurStats,
     reqs: Vec<Requirement>,
     build: ItemBuild,
     distribution: Option<Distribution>,
     rotatable: Option<Rotatables>,
+    optimizer: BestBonusFinder,
+    sets: Vec<ItemSet>,
+    ratings: RatingTable,
+    conversions: StatConversions,
+    race: Option<Race>,
+    weapon: Option<WeaponType>,
+    procs: Vec<ProcBonus>,
+    fight_length: Option<f64>,
+    // By how much the closest build missed each minimum when none reached them all.
+    shortfall: Vec<(Stat, f64)>,
 }
 
 impl Simulator {
-    pub fn new(cur_stats: CurStats, mut reqs: Vec<Requirement>, build: ItemBuild, rotatable: Option<Rotatables>) -> Self {
+    pub fn new(cur_stats: CurStats, reqs: Vec<Requirement>, build: ItemBuild, rotatable: Option<Rotatables>) -> Self {
+        return Self{cur_stats, reqs, build, distribution: None, rotatable, optimizer: BestBonusFinder::new(), sets: vec![], ratings: RatingTable::default(), conversions: StatConversions::new(), race: None, weapon: None, procs: vec![], fight_length: None, shortfall: vec![]};
+    }
+    // Caps given in percent or skill are converted with ratings, which the reports use as well.
+    pub fn set_ratings(&mut self, ratings: RatingTable) -> () {
+        self.ratings = ratings;
+    }
+    pub fn set_conversions(&mut self, conversions: StatConversions) -> () {
+        self.conversions = conversions;
+    }
+    pub fn set_requirements(&mut self, reqs: Vec<Requirement>) -> () {
+        self.reqs = reqs;
+    }
+    // Racial hit, expertise and dodge don't show up as rating, caps and current stats are adjusted for them here. Weapon only matters for racial expertise.
+    pub fn set_race(&mut self, race: Option<Race>, weapon: Option<WeaponType>) -> () {
+        self.race = race;
+        self.weapon = weapon;
+    }
+    // Procs of the equipped gear, which the current stats don't include.
+    pub fn set_procs(&mut self, procs: Vec<ProcBonus>) -> () {
+        self.procs = procs;
+    }
+    // Seconds, for on-use effects. Without it they count as if the fight never ended.
+    pub fn set_fight_length(&mut self, fight_length: Option<f64>) -> () {
+        self.fight_length = fight_length;
+    }
+    // Requirements on final stats with caps counting from the character's current stats, as the search needs them.
+    fn prepare_reqs(&self) -> Vec<Requirement> {
+        let mut reqs = self.reqs.clone();
+        let mut conversions = self.conversions.clone();
+        let mut base = self.cur_stats.clone();
+        if let Some(race) = self.race {
+            race.apply_multipliers(&mut conversions);
+            race.apply_base_stats(&mut base, &self.ratings);
+        }
+        // On-use effects of locked items aren't in the current stats either, rotated ones are added with the rest of their stats.
+        let rotated_slots = self.rotatable.as_ref().map_or(vec![], |rotatable| rotatable.slots_in_rotation().to_vec());
+        for (slot, opt_item) in self.build.item_iter() {
+            if let Some(item) = opt_item.as_ref().filter(|_| !rotated_slots.contains(&slot)) {
+                for effect in item.get_on_use() { effect.to_bonus(self.fight_length).apply_bonus(&mut base); }
+            }
+        }
+        // A proc counts with its average at the current haste, haste gained on top adds to it as if converted.
+        let haste = self.cur_stats.get_stat_val(Stat::HasteRate) as f64;
+        for proc in &self.procs {
+            let (average, slope) = proc.average(haste, &self.ratings);
+            base.add_stat(proc.get_stat(), average.round() as u32);
+            conversions.convert_through(Stat::HasteRate, proc.get_stat(), slope);
+        }
         for req in reqs.iter_mut() {
-            if let Requirement::RequirementCap(ref mut cap) = req {
-                cap.make_incremental(&cur_stats);
+            req.apply_conversions(&conversions);
+            match req {
+                Requirement::RequirementCap(ref mut cap) => {
+                    if let Some(race) = self.race { cap.lower(race.cap_bonus(cap.get_stat(), self.weapon), &self.ratings); }
+                    cap.convert_units(&self.ratings);
+                    cap.make_incremental(&base);
+                },
+                Requirement::RequirementPiecewise(ref mut piecewise) => {
+                    if let Some(race) = self.race { piecewise.lower(race.cap_bonus(piecewise.get_stat(), self.weapon), &self.ratings); }
+                    piecewise.convert_units(&self.ratings);
+                    piecewise.make_incremental(&base);
+                },
+                Requirement::RequirementMinimum(ref mut minimum) => {
+                    if let Some(race) = self.race { minimum.lower(race.cap_bonus(minimum.get_stat(), self.weapon), &self.ratings); }
+                    minimum.convert_units(&self.ratings);
+                    minimum.make_incremental(&base);
+                },
+                Requirement::RequirementBreakpoint(ref mut breakpoint) => {
+                    if let Some(race) = self.race { breakpoint.lower(race.cap_bonus(breakpoint.get_stat(), self.weapon), &self.ratings); }
+                    breakpoint.convert_units(&self.ratings);
+                    breakpoint.make_incremental(&base);
+                },
+                Requirement::RequirementDiminishing(ref mut diminishing) => {
+                    if let Some(race) = self.race { diminishing.lower(race.base_bonus(diminishing.get_stat()), &self.ratings); }
+                    diminishing.convert_units(&self.ratings);
+                    diminishing.make_incremental(&base);
+                },
+                Requirement::RequirementCustom(ref mut custom) => custom.make_incremental(&base),
+                Requirement::RequirementWeighted(_) => (),
             }
         }
-        return Self{reqs, build, distribution: None, rotatable};
+        return reqs;
+    }
+    // A finder without professions of its own keeps the character's.
+    pub fn set_bonus_finder(&mut self, mut optimizer: BestBonusFinder) -> () {
+        if optimizer.get_professions().is_empty() { optimizer.set_professions(self.optimizer.get_professions().to_vec()); }
+        self.optimizer = optimizer;
+    }
+    // Unlocks the gems and enchantments of these professions. Their extra sockets have to be on the items already.
+    pub fn set_professions(&mut self, professions: Vec<Profession>) -> () {
+        self.optimizer.set_professions(professions);
+    }
+    // The current stats have to hold the bonuses the locked items reach on their own, see ItemBuild::apply_set_bonuses.
+    pub fn set_item_sets(&mut self, sets: Vec<ItemSet>) -> () {
+        self.sets = sets;
     }
     fn next_build(&mut self, to_skip: usize) -> Option<CurStats> {
         let mut to_return = CurStats::new();
@@ -40,62 +147,112 @@ impl Simulator {
         if items_to_lock.is_some() {
             for rotatable_item in items_to_lock.unwrap() {
                 rotatable_item.get_stats_bonuses().apply_bonuses(&mut to_return);
+                for effect in rotatable_item.get_on_use() { effect.to_bonus(self.fight_length).apply_bonus(&mut to_return); }
                 self.build.lock_item(rotatable_item.clone());
             }
+            self.build.apply_set_bonuses(&self.sets, self.rotatable.as_ref().unwrap().slots_in_rotation(), &mut to_return);
             return Some(to_return);
         } else { return None; }
     }
-    pub fn run(&mut self, enable_gems: bool, enable_chants: bool, enable_food: bool, enable_prechant: bool, allow_tear: bool) -> () {
-        let optimizer = Arc::new(BestBonusFinder::new());
-        let reqs_arc = Arc::new(self.reqs.clone());
-        for food in optimizer.get_useful_food(&reqs_arc) {
-            // This cycle works only once if food is disabled, so it's fine.
+    // enable_prechant has the search decide enchantments before gems rather than after, which only changes how fast it is.
+    pub fn run(&mut self, enable_gems: bool, enable_chants: bool, enable_consumables: bool, enable_prechant: bool) -> () {
+        self.distribution = None;
+        self.shortfall.clear();
+        let reqs = self.prepare_reqs();
+        self.optimizer.set_fight_length(self.fight_length);
+        self.search_variants(&reqs, enable_gems, enable_chants, enable_consumables, enable_prechant);
+        // When no build reaches every minimum, the one getting closest to them tells by how much they're out of reach.
+        if self.distribution.is_none() && reqs.iter().any(|req| matches!(req, Requirement::RequirementMinimum(_))) {
+            let penalized: Vec<Requirement> = reqs.iter().map(|req| match req {
+                Requirement::RequirementMinimum(minimum) => Requirement::RequirementCap(minimum.to_penalty()),
+                _ => req.clone(),
+            }).collect();
+            self.search_variants(&penalized, enable_gems, enable_chants, enable_consumables, enable_prechant);
+            if let Some(attempt) = self.distribution.take() { self.shortfall = shortfalls(&reqs, attempt.get_stat_growth()); }
+        }
+    }
+    fn search_variants(&mut self, reqs: &[Requirement], enable_gems: bool, enable_chants: bool, enable_consumables: bool, enable_prechant: bool) -> () {
+        let mut loadouts = if enable_consumables { self.optimizer.get_consumable_loadouts(reqs) } else { vec![] };
+        // Without consumables (disabled or nothing useful) the cycle still has to run once.
+        if loadouts.is_empty() { loadouts.push(vec![]); }
+        for loadout in loadouts {
             let mut skip_that_much_variants = 0;
-            loop {
-                let cur_stats = self.next_build(skip_that_much_variants);
-                if cur_stats.is_none() {
-                    break;
-                } else {
-                    let mut cur_stats = cur_stats.unwrap();
-                    skip_that_much_variants += 1;
-                    // At the end of the loop this variant of equipment will already be processed, we will need the next one.
-                    if enable_food {
-                        food.get_bonuses().apply_bonuses(&mut cur_stats);
-                    }
-                    let mut main_state: Vec<Item> = Vec::new();
-                    for (_, opt_item) in self.build.item_iter() {
-                        if opt_item.is_some() { main_state.push(opt_item.as_ref().unwrap().clone()); }
-                    }
-                    let mut distr: Option<Distribution> = None;
-                    if enable_chants && !enable_gems {
-                        distr = Some(prechants_only(main_state.clone(), cur_stats.clone(), &reqs_arc, &optimizer));
-                    }
-                    if !enable_chants && enable_gems {
-                        distr = Some(solve_recursively(main_state.clone(), cur_stats.clone(), &reqs_arc, &optimizer, false, allow_tear, true));
-                    }
-                    if enable_chants && enable_gems && !enable_prechant {
-                        distr = Some(solve_recursively(main_state.clone(), cur_stats.clone(), &reqs_arc, &optimizer, true, allow_tear, true));
-                    }
-                    if enable_chants && enable_gems && enable_prechant {
-                        let chanted_distr = prechants_only(main_state.clone(), cur_stats.clone(), &reqs_arc, &optimizer);
-                        let distr_pre = solve_recursively(chanted_distr.get_items().to_vec(), chanted_distr.get_stat_growth().clone(), &reqs_arc, &optimizer, false, allow_tear, true);
-                        // Firstly chanting, then gems, second time both at the same time.
-                        let distr_post = solve_recursively(main_state, cur_stats.clone(), &reqs_arc, &optimizer, true, allow_tear, true);
-                        distr = if distr_pre.is_new_better(&Some(&distr_post)) { Some(distr_post) } else { Some(distr_pre) };
+            while let Some(mut cur_stats) = self.next_build(skip_that_much_variants) {
+                // At the end of the loop this variant of equipment will already be processed, we will need the next one.
+                skip_that_much_variants += 1;
+                for consumable in &loadout {
+                    consumable.get_bonuses().apply_bonuses(&mut cur_stats);
+                }
+                let mut main_state: Vec<Item> = Vec::new();
+                for (_, opt_item) in self.build.item_iter() {
+                    if opt_item.is_some() { main_state.push(opt_item.as_ref().unwrap().clone()); }
+                }
+                // Only a variant beating everything found so far is of interest, which lets the solver cut most of its branches.
+                let floor = self.distribution.as_ref().map(|distr| distr.get_gain());
+                let solver = Solver::new(main_state, reqs, &self.optimizer, enable_gems, enable_chants, enable_prechant);
+                if let Some(mut distr) = solver.solve(cur_stats, floor) {
+                    distr.set_consumables(loadout.clone());
+                    self.distribution = Some(distr);
+                }
+            }
+        }
+    }
+    // Evaluates the gems, enchantments and socket bonuses already present on the build without searching for better ones.
+    pub fn score(&mut self) -> () {
+        self.distribution = None;
+        self.shortfall.clear();
+        let reqs = self.prepare_reqs();
+        let mut skip_that_much_variants = 0;
+        while let Some(mut cur_stats) = self.next_build(skip_that_much_variants) {
+            skip_that_much_variants += 1;
+            let mut main_state: Vec<Item> = Vec::new();
+            for (_, opt_item) in self.build.item_iter() {
+                if let Some(item) = opt_item {
+                    for socket in item.get_sockets() {
+                        if let Some(gem) = socket.get_gem() { if !gem.is_meta() { gem.get_bonuses().apply_bonuses(&mut cur_stats); } }
                     }
-                    if self.distribution.is_none() || self.distribution.as_ref().unwrap().is_new_better(&distr.as_ref()) {
-                        self.distribution = distr;
-                        if enable_food { self.distribution.as_mut().unwrap().set_food(food.clone()) }
+                    if let Some(enchant) = item.get_enchantment() {
+                        let mut enchant = enchant.clone();
+                        enchant.average_on_use(self.fight_length);
+                        enchant.get_bonuses().apply_bonuses(&mut cur_stats);
                     }
+                    if item.sockets_match() { item.apply_socket_bonus(&mut cur_stats); }
+                    main_state.push(item.clone());
                 }
             }
-            if !enable_food { return; }
+            let distr = Distribution::new(cur_stats, &reqs, &main_state);
+            let missing = shortfalls(&reqs, distr.get_stat_growth());
+            if !missing.is_empty() {
+                let total = |shortfall: &[(Stat, f64)]| shortfall.iter().map(|(_, val)| val).sum::<f64>();
+                if self.shortfall.is_empty() || total(&missing) < total(&self.shortfall) { self.shortfall = missing; }
+                continue;
+            }
+            if self.distribution.is_none() || self.distribution.as_ref().unwrap().is_new_better(&Some(&distr)) {
+                self.distribution = Some(distr);
+            }
+        }
+    }
+    pub fn has_solution(&self) -> bool {
+        return self.distribution.is_some();
+    }
+    // Rating each minimum was missed by, empty if there's a solution or nothing to miss.
+    pub fn get_shortfall(&self) -> &[(Stat, f64)] {
+        if self.distribution.is_some() { return &[]; }
+        return &self.shortfall;
+    }
+    pub fn get_solution_items(&self) -> &[Item] {
+        match self.distribution {
+            None => &[],
+            Some(ref dis) => dis.get_items(),
         }
     }
+    pub fn report(&self) -> Option<OptimizationResult> {
+        return self.distribution.as_ref().map(|dis| OptimizationResult::new(dis, &self.ratings));
+    }
     pub fn result(&self) -> String {
         match self.distribution {
             None => String::new(),
-            Some(ref dis) => dis.to_string(),
+            Some(ref dis) => dis.to_string(&self.ratings),
         }
     }
     pub fn get_gain(&self) -> f64 {
@@ -106,70 +263,13 @@ impl Simulator {
     }
 }
 
-fn prechants_only(main_state: Vec<Item>, mut cur_stats: CurStats, reqs: &Arc<Vec<Requirement>>, optimizer: &Arc<BestBonusFinder>) -> Distribution {
-    let mut only_chants = Vec::new();
-    for (_item_ind, mut item) in main_state.iter().cloned().enumerate() {
-        if !item.is_enchanted() {
-            let enchantment = optimizer.get_best_enchantment_by_slot(item.get_slot(), &cur_stats, &reqs);
-            item.set_enchantment(&enchantment);
-        }
-        item.get_enchantment().as_ref().unwrap().get_bonuses().apply_bonuses(&mut cur_stats);
-        only_chants.push(item);
-    }
-    return Distribution::new(cur_stats.clone(), &reqs, &only_chants);
-}
-
-fn solve_recursively(main_state: Vec<Item>, cur_stats: CurStats, reqs: &Arc<Vec<Requirement>>, optimizer: &Arc<BestBonusFinder>, try_chanting: bool, allow_tear: bool, concurrent: bool) -> Distribution {
-    let mut distr_best = Distribution::new(cur_stats.clone(), reqs, &main_state);
-    let allow_tear_further = allow_tear && !main_state.iter().any(|item| item.get_sockets().iter().any(|socket| if socket.get_gem().is_none() { false } else { socket.get_gem().as_ref().unwrap().get_name() == "Nightmare's Tear" }));
-    let mut resulting_distrs = Vec::new();
-    let mut thread_handlers = Vec::new();
-    // TLDR: for each gem socket in each item fill gem, add its bonuses, chant item if needed, swap original item with chanted and gemmed, then solve for this state, in separate thread or not.
-    for (item_ind, mut item) in main_state.iter().cloned().enumerate() {
-        for ind_socket in 0..item.get_sockets().len() {
-            if !item.get_socket_mut(ind_socket).is_empty() { continue; }
-            let mut temp_stats = cur_stats.clone();
-            let best_gem = optimizer.get_best_gem(&temp_stats, reqs, allow_tear_further);
-            item.get_socket_mut(ind_socket).set_gem(&best_gem);
-            best_gem.get_bonuses().apply_bonuses(&mut temp_stats);
-            if try_chanting && !item.is_enchanted() {
-                let enchantment = optimizer.get_best_enchantment_by_slot(item.get_slot(), &temp_stats, reqs);
-                item.set_enchantment(&enchantment);
-                enchantment.get_bonuses().apply_bonuses(&mut temp_stats);
-            }
-            let mut altered_state = main_state.clone();
-            let item_tmp = item.clone();
-            altered_state[item_ind] = item_tmp;
-            if item.sockets_match() { item.apply_socket_bonus(&mut temp_stats); }
-            if concurrent {
-                let state_threaded = altered_state.clone();
-                let stats_threaded = temp_stats.clone();
-                let reqs_threaded = Arc::clone(reqs);
-                let optimizer_threaded = Arc::clone(optimizer);
-                thread_handlers.push(thread::spawn(move || {
-                    return solve_recursively(state_threaded, stats_threaded, &reqs_threaded, &optimizer_threaded, try_chanting, allow_tear_further, false);
-                }));
-            } else {
-                resulting_distrs.push(solve_recursively(altered_state, temp_stats, reqs, optimizer, try_chanting, allow_tear_further, false));
-            }
-            item.get_socket_mut(ind_socket).set_empty();
-            if try_chanting { item.remove_enchantment(); }
-        }
-    }
-    // Now collect and compare results.
-    if concurrent {
-        for handler in thread_handlers {
-            resulting_distrs.push(handler.join().unwrap_or(Distribution::new(cur_stats.clone(), reqs, &main_state)));
-        }
-    }
-    for distr in resulting_distrs {
-        if distr_best.is_new_better(&Some(&distr)) { distr_best = distr; }
-    }
-    return distr_best;
+fn shortfalls(reqs: &[Requirement], stats: &CurStats) -> Vec<(Stat, f64)> {
+    return reqs.iter().filter_map(|req| req.get_stat().map(|stat| (stat, req.shortfall(stats)))).filter(|(_, missing)| *missing > 0.0).collect();
 }
 
 #[cfg(test)]
 mod tests {
+    use std::str::FromStr;
     use crate::Stat;
     use super::*;
 
@@ -179,7 +279,24 @@ mod tests {
         let mut state = CurStats::new();
         state.set_stat(Stat::APR, 1383);
         cap.make_incremental(&state);
-        assert_eq!(cap.get_val(), 17);
+        assert_eq!(cap.get_val(), 17.0);
+    }
+    #[test]
+    fn caps_already_passed() {
+        use crate::char::ItemBuild;
+        use crate::items::{Item, GemSocket, Color};
+        use crate::catalog::Catalog;
+        use crate::{Bonuses, ItemSlot};
+        let catalog = Catalog::from_str("[[gem]]\nname = \"agile\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"Agility\", val = 20 }]\n").unwrap();
+        let mut my_build = ItemBuild::new();
+        my_build.lock_item(Item::new(String::from("chest"), ItemSlot::Chest, Bonuses::new(vec![]), vec![GemSocket::new(Color::Red)], None, None));
+        let mut stats = CurStats::new();
+        stats.set_stat(Stat::HitRate, 100);
+        let reqs = vec![Requirement::RequirementCap(RequirementCap::new(Stat::HitRate, 50, 2.0)), Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.0))];
+        let mut sim = Simulator::new(stats, reqs, my_build, None);
+        sim.set_bonus_finder(BestBonusFinder::from_catalog(&catalog));
+        sim.run(true, false, false, true);
+        assert_eq!(sim.get_gain(), 20.0);
     }
     #[test]
     fn all_systems_go() {
@@ -210,8 +327,8 @@ mod tests {
         rotatable.rotate(Item::new(String::from("ring2_1"), ItemSlot::Ring2, Bonuses::new(vec![Bonus::new(Stat::Agility, 100)]), vec![GemSocket::new(Color::Yellow)], Some(Bonus::new(Stat::Agility, 6)), None));
         rotatable.rotate(Item::new(String::from("ring2_2"), ItemSlot::Ring2, Bonuses::new(vec![Bonus::new(Stat::Agility, 0)]), vec![GemSocket::new(Color::Red)], Some(Bonus::new(Stat::Agility, 0)), None));
         let mut sim = Simulator::new(sp, reqs, my_build, Some(rotatable));
-        sim.run(true, true, true, true, false);
-        assert_eq!(6413.33, sim.distribution.expect("No solutions were found!").get_gain());
+        sim.run(true, true, true, true);
+        assert_eq!(6298.7300000000005, sim.distribution.expect("No solutions were found!").get_gain());
     }
     #[test]
     fn rotateless() {
@@ -237,8 +354,8 @@ mod tests {
         reqs.push(Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.91)));
         reqs.push(Requirement::RequirementWeighted(RequirementWeighted::new(Stat::CritRate, 1.42)));
         let mut sim = Simulator::new(sp, reqs, my_build, None);
-        sim.run(true, true, true, true, false);
-        assert_eq!(5746.259999999999, sim.distribution.expect("No solutions were found!").get_gain());
+        sim.run(true, true, true, true);
+        assert_eq!(5708.0599999999995, sim.distribution.expect("No solutions were found!").get_gain());
     }
     #[test]
     fn rotateless_and_foodless() {
@@ -264,8 +381,8 @@ mod tests {
         reqs.push(Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.91)));
         reqs.push(Requirement::RequirementWeighted(RequirementWeighted::new(Stat::CritRate, 1.42)));
         let mut sim = Simulator::new(sp, reqs, my_build, None);
-        sim.run(true, true, false, true, false);
-        assert_eq!(5669.86, sim.distribution.expect("No solutions were found!").get_gain());
+        sim.run(true, true, false, true);
+        assert_eq!(5631.659999999999, sim.distribution.expect("No solutions were found!").get_gain());
     }
     #[test]
     fn only_chants() {
@@ -291,8 +408,9 @@ mod tests {
         reqs.push(Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.91)));
         reqs.push(Requirement::RequirementWeighted(RequirementWeighted::new(Stat::CritRate, 1.42)));
         let mut sim = Simulator::new(sp, reqs, my_build, None);
-        sim.run(false, true, false, true, false);
-        assert_eq!(114.6, sim.distribution.expect("No solutions were found!").get_gain());
+        sim.run(false, true, false, true);
+        // The neck takes no enchantment, chest and feet get temp_agi.
+        assert_eq!(76.39999999999999, sim.distribution.expect("No solutions were found!").get_gain());
     }
     #[test]
     fn only_gems() {
@@ -318,7 +436,278 @@ mod tests {
         reqs.push(Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.91)));
         reqs.push(Requirement::RequirementWeighted(RequirementWeighted::new(Stat::CritRate, 1.42)));
         let mut sim = Simulator::new(sp, reqs, my_build, None);
-        sim.run(true, false, false, false, false);
+        sim.run(true, false, false, true);
         assert_eq!(5555.259999999999, sim.distribution.expect("No solutions were found!").get_gain());
     }
+    #[test]
+    fn scoring_keeps_build() {
+        use crate::char::{CurStats, ItemBuild};
+        use crate::items::{Item, Gem, GemSocket, Color};
+        use crate::{Bonus, Bonuses, ItemSlot, Stat};
+        let mut sp = CurStats::new();
+        sp.set_stat(Stat::APR, 1345);
+        let mut my_build = ItemBuild::new();
+        let mut item = Item::new(String::from("neck"), ItemSlot::Neck, Bonuses::new(vec![]), vec![GemSocket::new(Color::Blue)], Some(Bonus::new(Stat::Agility, 4)), None);
+        item.get_socket_mut(0).set_gem(&Gem::new(vec![Color::Red, Color::Blue], Bonuses::new(vec![Bonus::new(Stat::APR, 10)]), String::from("puissant")));
+        my_build.lock_item(item);
+        let item = Item::new(String::from("chest"), ItemSlot::Chest, Bonuses::new(vec![]), vec![GemSocket::new(Color::Yellow)], Some(Bonus::new(Stat::Agility, 6)), None);
+        my_build.lock_item(item);
+        let mut reqs: Vec<Requirement> = vec![];
+        reqs.push(Requirement::RequirementCap(RequirementCap::new(Stat::APR, 1400, 100.0)));
+        reqs.push(Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.91)));
+        let mut sim = Simulator::new(sp, reqs, my_build, None);
+        sim.score();
+        assert_eq!(1007.64, sim.get_gain());
+        assert!(sim.get_solution_items()[1].get_sockets()[0].is_empty());
+    }
+    #[test]
+    fn meta_activation_drives_gems() {
+        use crate::char::{CurStats, ItemBuild};
+        use crate::items::{Item, GemSocket, Color};
+        use crate::catalog::Catalog;
+        use crate::{Bonus, Bonuses, ItemSlot, Stat};
+        let catalog = Catalog::from_str("[[gem]]\nname = \"red\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"Agility\", val = 20 }]\n\n[[gem]]\nname = \"blue\"\ncolors = [\"Blue\"]\nbonuses = [{ stat = \"Agility\", val = 12 }]\n\n[[gem]]\nname = \"meta\"\ncolors = [\"Meta\"]\nbonuses = [{ stat = \"Agility\", val = 30 }]\nactivation = [{ rule = \"AtLeast\", color = \"Blue\", count = 2 }]\n").unwrap();
+        let mut my_build = ItemBuild::new();
+        let item = Item::new(String::from("head"), ItemSlot::Head, Bonuses::new(vec![]), vec![GemSocket::new(Color::Red), GemSocket::new(Color::Meta), GemSocket::new(Color::Red)], Some(Bonus::new(Stat::Agility, 1)), None);
+        my_build.lock_item(item);
+        let reqs = vec![Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.0))];
+        let mut sim = Simulator::new(CurStats::new(), reqs, my_build, None);
+        sim.set_bonus_finder(BestBonusFinder::from_catalog(&catalog));
+        sim.run(true, false, false, true);
+        // Two red gems and the socket bonus give 41, two blue gems activating the meta give 54.
+        assert_eq!(54.0, sim.get_gain());
+    }
+    #[test]
+    fn gem_limits_respected() {
+        use crate::char::{CurStats, ItemBuild};
+        use crate::items::{Item, GemSocket, Color};
+        use crate::catalog::Catalog;
+        use crate::{Bonuses, ItemSlot, Profession, Stat};
+        let catalog = Catalog::from_str("[[gem]]\nname = \"red\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"Agility\", val = 20 }]\n\n[[gem]]\nname = \"tear\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"Agility\", val = 30 }]\nlimit = { kind = \"UniqueEquipped\" }\n\n[[gem]]\nname = \"eye\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"Agility\", val = 40 }]\nlimit = { kind = \"Category\", category = \"Dragon's Eye\", count = 2 }\nprofession = \"Jewelcrafting\"\n").unwrap();
+        let mut my_build = ItemBuild::new();
+        let item = Item::new(String::from("chest"), ItemSlot::Chest, Bonuses::new(vec![]), vec![GemSocket::new(Color::Red), GemSocket::new(Color::Red), GemSocket::new(Color::Red), GemSocket::new(Color::Red)], None, None);
+        my_build.lock_item(item);
+        let reqs = vec![Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.0))];
+        let mut sim = Simulator::new(CurStats::new(), reqs.clone(), my_build.clone(), None);
+        sim.set_bonus_finder(BestBonusFinder::from_catalog(&catalog));
+        sim.run(true, false, false, true);
+        assert_eq!(90.0, sim.get_gain());
+        let mut finder = BestBonusFinder::from_catalog(&catalog);
+        finder.set_professions(vec![Profession::Jewelcrafting]);
+        let mut sim = Simulator::new(CurStats::new(), reqs, my_build, None);
+        sim.set_bonus_finder(finder);
+        sim.run(true, false, false, true);
+        assert_eq!(130.0, sim.get_gain());
+    }
+    #[test]
+    fn socket_bonus_weighed() {
+        use crate::char::{CurStats, ItemBuild};
+        use crate::items::{Item, GemSocket, Color};
+        use crate::catalog::Catalog;
+        use crate::{Bonus, Bonuses, ItemSlot, Stat};
+        let catalog = Catalog::from_str("[[gem]]\nname = \"red\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"Agility\", val = 20 }]\n\n[[gem]]\nname = \"yellow\"\ncolors = [\"Yellow\"]\nbonuses = [{ stat = \"Agility\", val = 12 }]\n").unwrap();
+        let mut my_build = ItemBuild::new();
+        my_build.lock_item(Item::new(String::from("chest"), ItemSlot::Chest, Bonuses::new(vec![]), vec![GemSocket::new(Color::Yellow)], Some(Bonus::new(Stat::Agility, 30)), None));
+        my_build.lock_item(Item::new(String::from("neck"), ItemSlot::Neck, Bonuses::new(vec![]), vec![GemSocket::new(Color::Yellow)], Some(Bonus::new(Stat::Agility, 4)), None));
+        let reqs = vec![Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.0))];
+        let mut sim = Simulator::new(CurStats::new(), reqs, my_build, None);
+        sim.set_bonus_finder(BestBonusFinder::from_catalog(&catalog));
+        sim.run(true, false, false, true);
+        // Matching the chest is worth its +30 bonus, matching the neck for +4 isn't.
+        assert_eq!(62.0, sim.get_gain());
+        let report = sim.report().unwrap();
+        assert!(!report.get_items()[0].is_socket_bonus_active() && report.get_items()[1].is_socket_bonus_active());
+    }
+    #[test]
+    fn consumables_alone() {
+        use crate::char::{CurStats, ItemBuild};
+        use crate::items::{Item, GemSocket, Color};
+        use crate::catalog::Catalog;
+        use crate::{Bonuses, ItemSlot, Stat};
+        let catalog = Catalog::from_str("[[gem]]\nname = \"red\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"Agility\", val = 20 }]\n\n[[consumable]]\nname = \"flask\"\ncategory = \"Flask\"\nbonuses = [{ stat = \"Agility\", val = 40 }]\n").unwrap();
+        let mut my_build = ItemBuild::new();
+        my_build.lock_item(Item::new(String::from("chest"), ItemSlot::Chest, Bonuses::new(vec![]), vec![GemSocket::new(Color::Red)], None, None));
+        let reqs = vec![Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.0))];
+        let mut sim = Simulator::new(CurStats::new(), reqs, my_build, None);
+        sim.set_bonus_finder(BestBonusFinder::from_catalog(&catalog));
+        // With gems and enchantments both off the consumables are still picked, the socket stays empty.
+        sim.run(false, false, true, true);
+        assert_eq!(40.0, sim.get_gain());
+    }
+    #[test]
+    fn run_again_after_changes() {
+        use crate::char::{CurStats, ItemBuild};
+        use crate::items::{Item, GemSocket, Color};
+        use crate::catalog::Catalog;
+        use crate::{Bonuses, ItemSlot, Stat};
+        let catalog = Catalog::from_str("[[gem]]\nname = \"red\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"Agility\", val = 20 }]\n").unwrap();
+        let mut my_build = ItemBuild::new();
+        my_build.lock_item(Item::new(String::from("chest"), ItemSlot::Chest, Bonuses::new(vec![]), vec![GemSocket::new(Color::Red)], None, None));
+        let mut sim = Simulator::new(CurStats::new(), vec![Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.0))], my_build, None);
+        sim.set_bonus_finder(BestBonusFinder::from_catalog(&catalog));
+        sim.run(true, false, false, true);
+        assert_eq!(20.0, sim.get_gain());
+        // The build of the first run gains less now and mustn't stand in the way of the second.
+        sim.set_requirements(vec![Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 0.5))]);
+        sim.run(true, false, false, true);
+        assert_eq!(10.0, sim.get_gain());
+    }
+    #[test]
+    fn racial_dodge_undiminished() {
+        use crate::char::{CurStats, ItemBuild};
+        use crate::items::{Item, GemSocket, Color};
+        use crate::catalog::Catalog;
+        use crate::race::Race;
+        use crate::{Bonuses, ItemSlot, Stat};
+        let catalog = Catalog::from_str("[[gem]]\nname = \"dodge\"\ncolors = [\"Blue\"]\nbonuses = [{ stat = \"Dodge\", val = 20 }]\n").unwrap();
+        let mut my_build = ItemBuild::new();
+        my_build.lock_item(Item::new(String::from("chest"), ItemSlot::Chest, Bonuses::new(vec![]), vec![GemSocket::new(Color::Blue)], None, None));
+        let mut stats = CurStats::new();
+        stats.set_stat(Stat::Dodge, 400);
+        let reqs = vec![Requirement::RequirementDiminishing(RequirementDiminishing::new(Stat::Dodge, 0.972, 116.890707, 1.0).unwrap())];
+        let gain_as = |race: Option<Race>| {
+            let mut sim = Simulator::new(stats.clone(), reqs.clone(), my_build.clone(), None);
+            sim.set_bonus_finder(BestBonusFinder::from_catalog(&catalog));
+            sim.set_race(race, None);
+            sim.run(true, false, false, true);
+            return sim.get_gain();
+        };
+        // A night elf's 2% dodge sits outside the curve, the gem diminishes just as much as without it.
+        assert!((gain_as(Some(Race::NightElf)) - gain_as(None)).abs() < 1e-9);
+    }
+    #[test]
+    fn sample_set() {
+        use crate::input::OptimizationInput;
+        // The eight slots of the sample set, which searching socket by socket never finished.
+        let mut sim = OptimizationInput::from_toml_str(include_str!("../data/feral.toml")).unwrap().into_simulator();
+        sim.run(true, true, true, true);
+        assert_eq!(6523.27, sim.get_gain());
+    }
+    #[test]
+    fn full_set() {
+        use crate::input::OptimizationInput;
+        // All seventeen slots gemmed and enchanted, with a meta gem and caps on hit, expertise and armor penetration.
+        let mut sim = OptimizationInput::from_toml_str(include_str!("../data/full-set.toml")).unwrap().into_simulator();
+        sim.run(true, true, true, true);
+        assert_eq!(7412.81, sim.get_gain());
+    }
+    #[test]
+    fn set_bonus_picks_variant() {
+        use crate::char::{CurStats, ItemBuild, Rotatables};
+        use crate::items::{Item, SetBonus};
+        use crate::{Bonus, Bonuses, ItemSlot, Stat};
+        let mut my_build = ItemBuild::new();
+        let mut head = Item::new(String::from("head"), ItemSlot::Head, Bonuses::new(vec![]), vec![], None, None);
+        head.set_item_set(Some(String::from("tier")));
+        my_build.lock_item(head);
+        let mut rotatable = Rotatables::new();
+        rotatable.rotate(Item::new(String::from("chest"), ItemSlot::Chest, Bonuses::new(vec![Bonus::new(Stat::Agility, 30)]), vec![], None, None));
+        let mut tier_chest = Item::new(String::from("tier chest"), ItemSlot::Chest, Bonuses::new(vec![Bonus::new(Stat::Agility, 20)]), vec![], None, None);
+        tier_chest.set_item_set(Some(String::from("tier")));
+        rotatable.rotate(tier_chest);
+        let reqs = vec![Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.0))];
+        let mut sim = Simulator::new(CurStats::new(), reqs, my_build, Some(rotatable));
+        sim.set_item_sets(vec![ItemSet::new(String::from("tier"), vec![SetBonus::new(2, Bonuses::new(vec![Bonus::new(Stat::Agility, 15)]))])]);
+        sim.score();
+        assert_eq!(35.0, sim.get_gain());
+        assert_eq!("tier chest", sim.get_solution_items()[1].get_name());
+    }
+    #[test]
+    fn conversions_weigh_final_stats() {
+        use crate::char::{CurStats, ItemBuild};
+        use crate::items::{Item, GemSocket, Color};
+        use crate::catalog::Catalog;
+        use crate::{Bonuses, ItemSlot, Stat};
+        let catalog = Catalog::from_str("[[gem]]\nname = \"bold\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"Strength\", val = 10 }]\n\n[[gem]]\nname = \"bright\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"AttackPower\", val = 20 }]\n").unwrap();
+        let mut my_build = ItemBuild::new();
+        my_build.lock_item(Item::new(String::from("chest"), ItemSlot::Chest, Bonuses::new(vec![]), vec![GemSocket::new(Color::Red)], None, None));
+        let reqs = vec![Requirement::RequirementWeighted(RequirementWeighted::new(Stat::AttackPower, 1.0))];
+        let mut conversions = StatConversions::new();
+        conversions.multiply(Stat::Strength, 1.1);
+        conversions.multiply(Stat::AttackPower, 1.1);
+        conversions.convert(Stat::Strength, Stat::AttackPower, 2.0);
+        let mut sim = Simulator::new(CurStats::new(), reqs, my_build, None);
+        sim.set_bonus_finder(BestBonusFinder::from_catalog(&catalog));
+        sim.set_conversions(conversions);
+        sim.run(true, false, false, true);
+        // 10 strength make 22 attack power before the attack power bonus, 20 attack power only 22 after it.
+        assert!((sim.get_gain() - 24.2).abs() < 1e-9);
+        assert_eq!(Some("bold"), sim.report().unwrap().get_items()[0].get_sockets()[0].get_gem());
+    }
+    #[test]
+    fn caster_gems() {
+        use crate::char::{CurStats, ItemBuild};
+        use crate::items::{Item, GemSocket, Color};
+        use crate::{Bonus, Bonuses, ItemSlot, Stat};
+        let mut my_build = ItemBuild::new();
+        my_build.lock_item(Item::new(String::from("robe"), ItemSlot::Chest, Bonuses::new(vec![]), vec![GemSocket::new(Color::Red), GemSocket::new(Color::Blue)], Some(Bonus::new(Stat::SpellPower, 8)), None));
+        let reqs = vec![Requirement::RequirementWeighted(RequirementWeighted::new(Stat::SpellPower, 1.0)), Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Spirit, 0.5))];
+        let mut sim = Simulator::new(CurStats::new(), reqs, my_build, None);
+        sim.run(true, false, false, true);
+        // Matching with purified for the bonus beats a second runed.
+        assert_eq!(48.0, sim.get_gain());
+        let report = sim.report().unwrap();
+        assert!(report.get_items()[0].get_sockets()[0].get_gem() == Some("runed") && report.get_items()[0].get_sockets()[1].get_gem() == Some("purified"));
+    }
+    #[test]
+    fn flask_over_elixirs() {
+        use crate::char::{CurStats, ItemBuild};
+        use crate::Stat;
+        let reqs = vec![Requirement::RequirementWeighted(RequirementWeighted::new(Stat::SpellPower, 1.0)), Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Intellect, 1.0))];
+        let mut sim = Simulator::new(CurStats::new(), reqs, ItemBuild::new(), None);
+        sim.run(true, true, true, true);
+        // Frost Wyrm's 125 spell power beats 58 from the battle and 45 intellect from the guardian elixir.
+        assert_eq!(173.0, sim.get_gain());
+        let names: Vec<String> = sim.report().unwrap().get_consumables().iter().map(|consumable| consumable.get_name().to_owned()).collect();
+        assert_eq!(names, vec!["Flask of the Frost Wyrm", "Scroll of Intellect VIII"]);
+    }
+    #[test]
+    fn on_use_by_fight_length() {
+        use crate::char::{CurStats, ItemBuild, Rotatables};
+        use crate::items::Item;
+        use crate::procs::OnUse;
+        use crate::{Bonus, Bonuses, ItemSlot, Stat};
+        let mut rotatable = Rotatables::new();
+        rotatable.rotate(Item::new(String::from("steady"), ItemSlot::Trinket1, Bonuses::new(vec![Bonus::new(Stat::AttackPower, 100)]), vec![], None, None));
+        let mut on_use = Item::new(String::from("on-use"), ItemSlot::Trinket1, Bonuses::new(vec![]), vec![], None, None);
+        on_use.set_on_use(vec![OnUse::new(Stat::AttackPower, 500, 20.0, 120.0)]);
+        rotatable.rotate(on_use);
+        let reqs = vec![Requirement::RequirementWeighted(RequirementWeighted::new(Stat::AttackPower, 1.0))];
+        let mut sim = Simulator::new(CurStats::new(), reqs, ItemBuild::new(), Some(rotatable));
+        sim.score();
+        assert!(sim.get_gain() == 100.0 && sim.get_solution_items()[0].get_name() == "steady");
+        // Used on pull and at two minutes, a three minute fight has it up 40 seconds out of 180.
+        sim.set_fight_length(Some(180.0));
+        sim.score();
+        assert!(sim.get_gain() == 111.0 && sim.get_solution_items()[0].get_name() == "on-use");
+    }
+    #[test]
+    fn custom_product() {
+        use std::sync::Arc;
+        use crate::char::{CurStats, ItemBuild};
+        use crate::items::{Item, GemSocket, Color};
+        use crate::catalog::Catalog;
+        use crate::{Bonuses, ItemSlot, Stat};
+        struct CritTimesPower;
+        impl GainFunction for CritTimesPower {
+            fn gain(&self, stats: &CurStats) -> f64 {
+                return (stats.get_stat_val(Stat::CritRate) * stats.get_stat_val(Stat::AttackPower)) as f64;
+            }
+        }
+        let catalog = Catalog::from_str("[[gem]]\nname = \"crit\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"CritRate\", val = 20 }]\n\n[[gem]]\nname = \"power\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"AttackPower\", val = 40 }]\n").unwrap();
+        let mut my_build = ItemBuild::new();
+        my_build.lock_item(Item::new(String::from("chest"), ItemSlot::Chest, Bonuses::new(vec![]), vec![GemSocket::new(Color::Red), GemSocket::new(Color::Red)], None, None));
+        let mut stats = CurStats::new();
+        stats.set_stat(Stat::CritRate, 100);
+        stats.set_stat(Stat::AttackPower, 200);
+        let reqs = vec![Requirement::RequirementCustom(RequirementCustom::new(String::from("crit times attack power"), Arc::new(CritTimesPower)))];
+        let mut sim = Simulator::new(stats, reqs, my_build, None);
+        sim.set_bonus_finder(BestBonusFinder::from_catalog(&catalog));
+        sim.run(true, false, false, true);
+        // Either pair of gems makes 28000 out of 20000, one of each makes 120 * 240.
+        assert_eq!(sim.get_gain(), 8800.0);
+        let report = sim.report().unwrap();
+        let gems: Vec<&str> = report.get_items()[0].get_sockets().iter().map(|socket| socket.get_gem().unwrap()).collect();
+        assert!(gems.contains(&"crit") && gems.contains(&"power"));
+    }
 }
\ No newline at end of file
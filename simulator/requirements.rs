@@ -1,57 +1,833 @@
-use crate::Stat;
+use std::sync::Arc;
+use crate::{Bonus, Bonuses, Stat};
 use crate::char::CurStats;
+use crate::conversions::StatConversions;
+use crate::ratings::{RatingTable, RatingUnit};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum Requirement {
+    #[serde(rename = "cap")]
     RequirementCap(RequirementCap),
+    #[serde(rename = "weighted")]
     RequirementWeighted(RequirementWeighted),
+    #[serde(rename = "piecewise")]
+    RequirementPiecewise(RequirementPiecewise),
+    #[serde(rename = "diminishing")]
+    RequirementDiminishing(RequirementDiminishing),
+    #[serde(rename = "minimum")]
+    RequirementMinimum(RequirementMinimum),
+    #[serde(rename = "breakpoint")]
+    RequirementBreakpoint(RequirementBreakpoint),
+    // Only made in code, input files can't refer to one and writing it to one fails with its name.
+    #[serde(skip_deserializing, serialize_with = "custom_unserializable")]
+    RequirementCustom(RequirementCustom),
+}
+
+impl Requirement {
+    // None for custom requirements, which can score any stats.
+    pub fn get_stat(&self) -> Option<Stat> {
+        match self {
+            Requirement::RequirementCap(cap) => Some(cap.get_stat()),
+            Requirement::RequirementWeighted(weighted) => Some(weighted.get_stat()),
+            Requirement::RequirementPiecewise(piecewise) => Some(piecewise.get_stat()),
+            Requirement::RequirementDiminishing(diminishing) => Some(diminishing.get_stat()),
+            Requirement::RequirementMinimum(minimum) => Some(minimum.get_stat()),
+            Requirement::RequirementBreakpoint(breakpoint) => Some(breakpoint.get_stat()),
+            Requirement::RequirementCustom(_) => None,
+        }
+    }
+    // Final value of the stat with everything converted into it, see StatConversions. Always 0 for custom requirements.
+    pub fn value_of(&self, stats: &CurStats) -> f64 {
+        match self {
+            Requirement::RequirementCap(cap) => cap.value_of(stats),
+            Requirement::RequirementWeighted(weighted) => weighted.value_of(stats),
+            Requirement::RequirementPiecewise(piecewise) => piecewise.value_of(stats),
+            Requirement::RequirementDiminishing(diminishing) => diminishing.value_of(stats),
+            Requirement::RequirementMinimum(minimum) => minimum.value_of(stats),
+            Requirement::RequirementBreakpoint(breakpoint) => breakpoint.value_of(stats),
+            Requirement::RequirementCustom(_) => 0.0,
+        }
+    }
+    // How much one point of stat adds to value_of, see shares for custom requirements.
+    pub fn contribution(&self, stat: Stat) -> f64 {
+        match self {
+            Requirement::RequirementCap(cap) => contribution(cap.stat, &cap.sources, stat),
+            Requirement::RequirementWeighted(weighted) => contribution(weighted.stat, &weighted.sources, stat),
+            Requirement::RequirementPiecewise(piecewise) => contribution(piecewise.stat, &piecewise.sources, stat),
+            Requirement::RequirementDiminishing(diminishing) => contribution(diminishing.stat, &diminishing.sources, stat),
+            Requirement::RequirementMinimum(minimum) => contribution(minimum.stat, &minimum.sources, stat),
+            Requirement::RequirementBreakpoint(breakpoint) => contribution(breakpoint.stat, &breakpoint.sources, stat),
+            Requirement::RequirementCustom(_) => 0.0,
+        }
+    }
+    pub fn apply_conversions(&mut self, conversions: &StatConversions) -> () {
+        match self {
+            Requirement::RequirementCap(cap) => cap.sources = conversions.sources_of(cap.stat),
+            Requirement::RequirementWeighted(weighted) => weighted.sources = conversions.sources_of(weighted.stat),
+            Requirement::RequirementPiecewise(piecewise) => piecewise.sources = conversions.sources_of(piecewise.stat),
+            Requirement::RequirementDiminishing(diminishing) => diminishing.sources = conversions.sources_of(diminishing.stat),
+            Requirement::RequirementMinimum(minimum) => minimum.sources = conversions.sources_of(minimum.stat),
+            Requirement::RequirementBreakpoint(breakpoint) => breakpoint.sources = conversions.sources_of(breakpoint.stat),
+            Requirement::RequirementCustom(_) => (),
+        }
+    }
+    // Gains from stats on only differ between stats that add different values to key here.
+    pub(crate) fn future_state(&self, stats: &CurStats, key: &mut Vec<u64>) -> () {
+        key.push(match self {
+            Requirement::RequirementCap(cap) => cap.value_of(stats).min(cap.get_val()).to_bits(),
+            // Every point is worth the same.
+            Requirement::RequirementWeighted(_) => return,
+            // Past the last breakpoint every point is worth the same.
+            Requirement::RequirementPiecewise(piecewise) => piecewise.value_of(stats).min(piecewise.last_breakpoint()).to_bits(),
+            // Every point is worth a little less than the one before.
+            Requirement::RequirementDiminishing(diminishing) => diminishing.value_of(stats).to_bits(),
+            // Gains nothing, but whether the rest of the build can still reach it depends on how far it is.
+            Requirement::RequirementMinimum(minimum) => minimum.value_of(stats).min(minimum.get_val()).to_bits(),
+            Requirement::RequirementBreakpoint(breakpoint) => breakpoint.value_of(stats).min(breakpoint.last_threshold()).to_bits(),
+            // Any stat can matter.
+            Requirement::RequirementCustom(_) => {
+                key.extend(Stat::ALL.iter().map(|stat| stats.get_stat_val(*stat) as u64));
+                return;
+            },
+        });
+    }
+    // Most one more point of stat can add to the gain of reference or of any stats above it, infinite without such a bound.
+    pub(crate) fn rate_bound(&self, stat: Stat, reference: &CurStats) -> f64 {
+        let contribution = self.contribution(stat);
+        match self {
+            Requirement::RequirementCap(cap) => cap.get_weight() * contribution,
+            Requirement::RequirementWeighted(weighted) => weighted.get_weight() * contribution,
+            Requirement::RequirementPiecewise(piecewise) => piecewise.slope_from(self.value_of(reference)) * contribution,
+            Requirement::RequirementDiminishing(diminishing) => diminishing.slope_from(self.value_of(reference)) * contribution,
+            Requirement::RequirementMinimum(_) => 0.0,
+            // Right below a threshold a single point gains all of it.
+            Requirement::RequirementBreakpoint(_) => if contribution > 0.0 { f64::INFINITY } else { 0.0 },
+            Requirement::RequirementCustom(custom) => custom.slope_bound(stat, reference),
+        }
+    }
+    // How much stats fall short of a minimum, 0 for met minimums and every other requirement.
+    pub fn shortfall(&self, stats: &CurStats) -> f64 {
+        match self {
+            Requirement::RequirementMinimum(minimum) => return (minimum.get_val() - minimum.value_of(stats)).max(0.0),
+            _ => return 0.0,
+        }
+    }
+}
+
+impl GainFunction for Requirement {
+    fn gain(&self, stats: &CurStats) -> f64 {
+        match self {
+            Requirement::RequirementCap(cap) => cap.gain(stats),
+            Requirement::RequirementWeighted(weighted) => weighted.gain(stats),
+            Requirement::RequirementPiecewise(piecewise) => piecewise.gain(stats),
+            Requirement::RequirementDiminishing(diminishing) => diminishing.gain(stats),
+            Requirement::RequirementMinimum(minimum) => minimum.gain(stats),
+            Requirement::RequirementBreakpoint(breakpoint) => breakpoint.gain(stats),
+            Requirement::RequirementCustom(custom) => custom.gain(stats),
+        }
+    }
+    fn gain_by_bonus(&self, bonus: &Bonus, reference: &CurStats) -> f64 {
+        match self {
+            Requirement::RequirementCap(cap) => cap.gain_by_bonus(bonus, reference),
+            Requirement::RequirementWeighted(weighted) => weighted.gain_by_bonus(bonus, reference),
+            Requirement::RequirementPiecewise(piecewise) => piecewise.gain_by_bonus(bonus, reference),
+            Requirement::RequirementDiminishing(diminishing) => diminishing.gain_by_bonus(bonus, reference),
+            Requirement::RequirementMinimum(minimum) => minimum.gain_by_bonus(bonus, reference),
+            Requirement::RequirementBreakpoint(breakpoint) => breakpoint.gain_by_bonus(bonus, reference),
+            Requirement::RequirementCustom(custom) => custom.gain_by_bonus(bonus, reference),
+        }
+    }
+}
+
+impl std::fmt::Display for Requirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Requirement::RequirementCap(cap) => match (cap.units, RatingTable::unit_of(cap.stat)) {
+                (Some(units), Some(unit)) => write!(f, "{} capped at {}{}, weight {}", cap.stat, units, unit, cap.weight),
+                _ => write!(f, "{} capped at {}, weight {}", cap.stat, cap.val, cap.weight),
+            },
+            Requirement::RequirementWeighted(weighted) => write!(f, "{}, weight {}", weighted.stat, weighted.weight),
+            Requirement::RequirementPiecewise(piecewise) => {
+                write!(f, "{}", piecewise.stat)?;
+                for region in &piecewise.regions {
+                    match (region.units, RatingTable::unit_of(piecewise.stat)) {
+                        (Some(units), Some(unit)) => write!(f, ", weight {} up to {}{}", region.weight, units, unit)?,
+                        _ => write!(f, ", weight {} up to {}", region.weight, region.val)?,
+                    }
+                }
+                write!(f, ", weight {} after", piecewise.weight_after)
+            },
+            Requirement::RequirementMinimum(minimum) => match (minimum.units, RatingTable::unit_of(minimum.stat)) {
+                (Some(units), Some(unit)) => write!(f, "{} at least {}{}", minimum.stat, units, unit),
+                _ => write!(f, "{} at least {}", minimum.stat, minimum.val),
+            },
+            Requirement::RequirementBreakpoint(breakpoint) => {
+                write!(f, "{}", breakpoint.stat)?;
+                for threshold in &breakpoint.thresholds {
+                    match (threshold.units, RatingTable::unit_of(breakpoint.stat)) {
+                        (Some(units), Some(unit)) => write!(f, ", gain {} at {}{}", threshold.gain, units, unit)?,
+                        _ => write!(f, ", gain {} at {}", threshold.gain, threshold.val)?,
+                    }
+                }
+                return Ok(());
+            },
+            Requirement::RequirementCustom(custom) => write!(f, "{}", custom.name),
+            Requirement::RequirementDiminishing(diminishing) => write!(f, "{} with diminishing returns (k {}, cap {}{}), weight {}", diminishing.stat, diminishing.k, diminishing.cap, RatingTable::unit_of(diminishing.stat).unwrap(), diminishing.weight),
+        }
+    }
+}
+
+// Scores what a build adds to the character. Gains must never drop as stats grow, the search leaves out gems and consumables
+// that can't beat others on that ground. Built-in requirements get stats added on top of the current ones, custom ones the full stats.
+pub trait GainFunction {
+    fn gain(&self, stats: &CurStats) -> f64;
+    // What bonus adds to the gain of reference.
+    fn gain_by_bonus(&self, bonus: &Bonus, reference: &CurStats) -> f64 {
+        let mut next = reference.clone();
+        bonus.apply_bonus(&mut next);
+        return self.gain(&next) - self.gain(reference);
+    }
+    // Most one more point of stat can add to the gain of reference or of any stats above it. Without a finite bound the search
+    // only knows what the most of every stat a build could add would gain, which cuts fewer branches.
+    fn slope_bound(&self, _stat: Stat, _reference: &CurStats) -> f64 {
+        return f64::INFINITY;
+    }
 }
 
+// A requirement scored by a GainFunction from outside the crate, like the product of crit and attack power. Conversions don't
+// apply to it, the function gets the character's stats as they are.
 #[derive(Clone)]
+pub struct RequirementCustom {
+    name: String,
+    function: Arc<dyn GainFunction>,
+    // Current stats, like for the other requirements only what the build adds to them is gain.
+    base: CurStats,
+}
+
+impl RequirementCustom {
+    pub fn new(name: String, function: Arc<dyn GainFunction>) -> Self {
+        return Self{name, function, base: CurStats::new()};
+    }
+    pub fn get_name(&self) -> &str { return &self.name; }
+    pub fn make_incremental(&mut self, cur_stats: &CurStats) -> () {
+        self.base = cur_stats.clone();
+    }
+}
+
+fn custom_unserializable<S: serde::Serializer>(custom: &RequirementCustom, _serializer: S) -> Result<S::Ok, S::Error> {
+    return Err(serde::ser::Error::custom(format!("custom requirement {} only exists in code", custom.name)));
+}
+
+impl GainFunction for RequirementCustom {
+    fn gain(&self, stats: &CurStats) -> f64 {
+        return self.function.gain(&self.base.sum_of(stats)) - self.function.gain(&self.base);
+    }
+    fn gain_by_bonus(&self, bonus: &Bonus, reference: &CurStats) -> f64 {
+        return self.function.gain_by_bonus(bonus, &self.base.sum_of(reference));
+    }
+    fn slope_bound(&self, stat: Stat, reference: &CurStats) -> f64 {
+        return self.function.slope_bound(stat, &self.base.sum_of(reference));
+    }
+}
+
+// What bonuses add to the value of every requirement, with a share per stat for custom requirements since they can weigh them in any way.
+// As gains never drop with more stats, bonuses with at least the shares of others gain at least as much.
+pub(crate) fn shares(reqs: &[Requirement], bonuses: &Bonuses) -> Vec<f64> {
+    let mut res = Vec::new();
+    for req in reqs {
+        match req {
+            Requirement::RequirementCustom(_) => res.extend(Stat::ALL.iter().map(|stat| bonuses.iter().filter(|bonus| bonus.get_stat() == *stat).map(|bonus| bonus.get_val() as f64).sum::<f64>())),
+            _ => res.push(bonuses.iter().map(|bonus| req.contribution(bonus.get_stat()) * bonus.get_val() as f64).sum()),
+        }
+    }
+    return res;
+}
+
+// Without conversions applied a requirement only counts its own stat.
+fn value_of(stat: Stat, sources: &[(Stat, f64)], stats: &CurStats) -> f64 {
+    if sources.is_empty() { return stats.get_stat_val(stat) as f64; }
+    return sources.iter().map(|&(source, coef)| coef * stats.get_stat_val(source) as f64).sum();
+}
+
+fn contribution(stat: Stat, sources: &[(Stat, f64)], of: Stat) -> f64 {
+    if sources.is_empty() { return if of == stat { 1.0 } else { 0.0 }; }
+    return sources.iter().find(|(source, _)| *source == of).map_or(0.0, |(_, coef)| *coef);
+}
+
+// How caps, minimums, thresholds and breakpoints are written in input files: exactly one of a rating, a percent or (for expertise
+// and defense) a skill value.
+#[derive(Clone, Serialize, Deserialize)]
+struct UnitValue {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    val: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    percent: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    skill: Option<f64>,
+}
+
+impl UnitValue {
+    // The rating and the units it was given in, if any. What names the value in errors.
+    fn parse(&self, stat: Stat, what: &str) -> Result<(f64, Option<f64>), String> {
+        let unit = RatingTable::unit_of(stat);
+        match (self.val, self.percent, self.skill) {
+            (Some(val), None, None) => Ok((val as f64, None)),
+            (None, Some(units), None) if unit == Some(RatingUnit::Percent) => Ok((0.0, Some(units))),
+            (None, None, Some(units)) if unit == Some(RatingUnit::Skill) => Ok((0.0, Some(units))),
+            (None, Some(_), None) | (None, None, Some(_)) => Err(format!("a {} of {} can't be given in {}", what, stat, if self.percent.is_some() { "percent" } else { "skill" })),
+            _ => Err(format!("a {} needs exactly one of val, percent and skill", what)),
+        }
+    }
+    fn of(stat: Stat, val: f64, units: Option<f64>) -> Self {
+        let in_skill = RatingTable::unit_of(stat) == Some(RatingUnit::Skill);
+        return Self{val: if units.is_none() { Some(val.round() as u32) } else { None }, percent: units.filter(|_| !in_skill), skill: units.filter(|_| in_skill)};
+    }
+}
+
+// The rating of a value given in units, which only values of ratings are.
+fn convert_units(stat: Stat, val: &mut f64, units: Option<f64>, ratings: &RatingTable) -> () {
+    if let Some(units) = units {
+        *val = ratings.to_rating(stat, units).expect("Values in units are only made for ratings!") as f64;
+    }
+}
+
+// Lowers a value by units the character gets from elsewhere, like racial hit. Must come before convert_units.
+// A value given as rating loses the rounded rating those units are worth.
+fn lower(stat: Stat, val: &mut f64, own: &mut Option<f64>, units: f64, ratings: &RatingTable) -> () {
+    if units == 0.0 { return; }
+    match own {
+        Some(own) => *own = (*own - units).max(0.0),
+        None => *val = (*val - (units * ratings.rating_per_unit(stat).unwrap_or(0.0)).round()).max(0.0),
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "CapSpec", into = "CapSpec")]
 pub struct RequirementCap {
     stat: Stat,
-    val: u32,
+    val: f64,
+    // The cap in percent (skill for expertise and defense) when it was given that way, val only holds the rating after convert_units.
+    units: Option<f64>,
     weight: f64,
+    sources: Vec<(Stat, f64)>,
 }
 
 impl RequirementCap {
     pub fn new(stat: Stat, val: u32, weight: f64) -> Self {
-        return Self{stat, val, weight};
+        return Self{stat, val: val as f64, units: None, weight, sources: vec![]};
+    }
+    // None if the stat isn't a combat rating.
+    pub fn new_in_units(stat: Stat, units: f64, weight: f64) -> Option<Self> {
+        RatingTable::unit_of(stat)?;
+        return Some(Self{stat, val: 0.0, units: Some(units), weight, sources: vec![]});
     }
     pub fn get_stat(&self) -> Stat { return self.stat; }
-    pub fn get_val(&self) -> u32 { return self.val; }
+    pub fn get_val(&self) -> f64 { return self.val; }
+    pub fn get_units(&self) -> Option<f64> { return self.units; }
     pub fn get_weight(&self) -> f64 { return self.weight; }
+    pub fn convert_units(&mut self, ratings: &RatingTable) -> () {
+        convert_units(self.stat, &mut self.val, self.units, ratings);
+    }
+    // Lowers the cap by units the character gets from elsewhere, like racial hit. Must come before convert_units.
+    pub fn lower(&mut self, units: f64, ratings: &RatingTable) -> () {
+        lower(self.stat, &mut self.val, &mut self.units, units, ratings);
+    }
+    pub fn value_of(&self, stats: &CurStats) -> f64 {
+        return value_of(self.stat, &self.sources, stats);
+    }
+    // A cap the current stats already pass is worth nothing more.
     pub fn make_incremental(&mut self, cur_stats: &CurStats) -> () {
-        self.val -= cur_stats.get_stat_val(self.stat);
+        self.val = (self.val - self.value_of(cur_stats)).max(0.0);
     }
-    pub fn calculate_gain(&self, new_val: u32) -> f64 {
+    pub fn calculate_gain(&self, new_val: f64) -> f64 {
         if new_val > self.val {
-            return self.val as f64 * self.weight;
+            return self.val * self.weight;
         } else {
-            return new_val as f64 * self.weight;
+            return new_val * self.weight;
         }
     }
-    pub fn calculate_gain_incremental(&self, inc: u32, reference: u32) -> f64 {
+    pub fn calculate_gain_incremental(&self, inc: f64, reference: f64) -> f64 {
         if reference > self.val { return 0.0; }
         if inc + reference > self.val {
-            return (self.val-reference) as f64 * self.weight;
-        } else { return inc as f64 * self.weight; }
+            return (self.val - reference) * self.weight;
+        } else { return inc * self.weight; }
+    }
+}
+
+impl GainFunction for RequirementCap {
+    fn gain(&self, stats: &CurStats) -> f64 {
+        return self.calculate_gain(self.value_of(stats));
+    }
+    fn gain_by_bonus(&self, bonus: &Bonus, reference: &CurStats) -> f64 {
+        return self.calculate_gain_incremental(contribution(self.stat, &self.sources, bonus.get_stat()) * bonus.get_val() as f64, self.value_of(reference));
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CapSpec {
+    stat: Stat,
+    #[serde(flatten)]
+    value: UnitValue,
+    weight: f64,
+}
+
+impl TryFrom<CapSpec> for RequirementCap {
+    type Error = String;
+    fn try_from(spec: CapSpec) -> Result<Self, Self::Error> {
+        let (val, units) = spec.value.parse(spec.stat, "cap")?;
+        return Ok(Self{stat: spec.stat, val, units, weight: spec.weight, sources: vec![]});
     }
 }
 
+impl From<RequirementCap> for CapSpec {
+    fn from(cap: RequirementCap) -> Self {
+        return Self{stat: cap.stat, value: UnitValue::of(cap.stat, cap.val, cap.units), weight: cap.weight};
+    }
+}
+
+// A value the stat has to reach before anything else counts, builds short of it aren't solutions at all. Gains nothing by itself.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "MinimumSpec", into = "MinimumSpec")]
+pub struct RequirementMinimum {
+    stat: Stat,
+    val: f64,
+    units: Option<f64>,
+    sources: Vec<(Stat, f64)>,
+}
+
+impl RequirementMinimum {
+    pub fn new(stat: Stat, val: u32) -> Self {
+        return Self{stat, val: val as f64, units: None, sources: vec![]};
+    }
+    // None if the stat isn't a combat rating.
+    pub fn new_in_units(stat: Stat, units: f64) -> Option<Self> {
+        RatingTable::unit_of(stat)?;
+        return Some(Self{stat, val: 0.0, units: Some(units), sources: vec![]});
+    }
+    pub fn get_stat(&self) -> Stat { return self.stat; }
+    pub fn get_val(&self) -> f64 { return self.val; }
+    pub fn get_units(&self) -> Option<f64> { return self.units; }
+    pub fn convert_units(&mut self, ratings: &RatingTable) -> () {
+        convert_units(self.stat, &mut self.val, self.units, ratings);
+    }
+    // Same as RequirementCap::lower.
+    pub fn lower(&mut self, units: f64, ratings: &RatingTable) -> () {
+        lower(self.stat, &mut self.val, &mut self.units, units, ratings);
+    }
+    pub fn value_of(&self, stats: &CurStats) -> f64 {
+        return value_of(self.stat, &self.sources, stats);
+    }
+    pub fn make_incremental(&mut self, cur_stats: &CurStats) -> () {
+        self.val -= self.value_of(cur_stats);
+    }
+    // A cap at the minimum worth so much that getting closer to it beats any other gain, for finding the nearest miss.
+    pub(crate) fn to_penalty(&self) -> RequirementCap {
+        return RequirementCap{stat: self.stat, val: self.val, units: None, weight: 1e6, sources: self.sources.clone()};
+    }
+}
+
+// Never gains anything, see Requirement::shortfall.
+impl GainFunction for RequirementMinimum {
+    fn gain(&self, _stats: &CurStats) -> f64 {
+        return 0.0;
+    }
+    fn gain_by_bonus(&self, _bonus: &Bonus, _reference: &CurStats) -> f64 {
+        return 0.0;
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct MinimumSpec {
+    stat: Stat,
+    #[serde(flatten)]
+    value: UnitValue,
+}
+
+impl TryFrom<MinimumSpec> for RequirementMinimum {
+    type Error = String;
+    fn try_from(spec: MinimumSpec) -> Result<Self, Self::Error> {
+        let (val, units) = spec.value.parse(spec.stat, "minimum")?;
+        return Ok(Self{stat: spec.stat, val, units, sources: vec![]});
+    }
+}
+
+impl From<RequirementMinimum> for MinimumSpec {
+    fn from(minimum: RequirementMinimum) -> Self {
+        return Self{stat: minimum.stat, value: UnitValue::of(minimum.stat, minimum.val, minimum.units)};
+    }
+}
+
+// A threshold of a breakpoint requirement, reaching val gains gain at once.
 #[derive(Clone)]
+pub struct BreakpointThreshold {
+    val: f64,
+    units: Option<f64>,
+    gain: f64,
+}
+
+impl BreakpointThreshold {
+    pub fn new(val: u32, gain: f64) -> Self {
+        return Self{val: val as f64, units: None, gain};
+    }
+    pub fn new_in_units(units: f64, gain: f64) -> Self {
+        return Self{val: 0.0, units: Some(units), gain};
+    }
+    pub fn get_val(&self) -> f64 { return self.val; }
+    pub fn get_units(&self) -> Option<f64> { return self.units; }
+    pub fn get_gain(&self) -> f64 { return self.gain; }
+}
+
+// Fixed gains for reaching each threshold, like an extra tick of a damage over time spell at some haste. Points between them are worth nothing.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "BreakpointSpec", into = "BreakpointSpec")]
+pub struct RequirementBreakpoint {
+    stat: Stat,
+    thresholds: Vec<BreakpointThreshold>,
+    sources: Vec<(Stat, f64)>,
+}
+
+impl RequirementBreakpoint {
+    // None without thresholds, with negative gains or with thresholds not going up.
+    pub fn new(stat: Stat, thresholds: Vec<BreakpointThreshold>) -> Option<Self> {
+        if thresholds.is_empty() || thresholds.iter().any(|threshold| threshold.gain < 0.0) { return None; }
+        let in_units = thresholds[0].units.is_some();
+        if in_units && RatingTable::unit_of(stat).is_none() { return None; }
+        if thresholds.iter().any(|threshold| threshold.units.is_some() != in_units) { return None; }
+        let bounds: Vec<f64> = thresholds.iter().map(|threshold| threshold.units.unwrap_or(threshold.val)).collect();
+        if bounds.windows(2).any(|pair| pair[0] >= pair[1]) { return None; }
+        return Some(Self{stat, thresholds, sources: vec![]});
+    }
+    pub fn get_stat(&self) -> Stat { return self.stat; }
+    pub fn get_thresholds(&self) -> &[BreakpointThreshold] { return &self.thresholds; }
+    fn last_threshold(&self) -> f64 {
+        return self.thresholds.last().unwrap().val;
+    }
+    pub fn convert_units(&mut self, ratings: &RatingTable) -> () {
+        for threshold in &mut self.thresholds {
+            convert_units(self.stat, &mut threshold.val, threshold.units, ratings);
+        }
+    }
+    // Same as RequirementCap::lower for every threshold.
+    pub fn lower(&mut self, units: f64, ratings: &RatingTable) -> () {
+        for threshold in &mut self.thresholds {
+            lower(self.stat, &mut threshold.val, &mut threshold.units, units, ratings);
+        }
+    }
+    pub fn value_of(&self, stats: &CurStats) -> f64 {
+        return value_of(self.stat, &self.sources, stats);
+    }
+    pub fn make_incremental(&mut self, cur_stats: &CurStats) -> () {
+        let reference = self.value_of(cur_stats);
+        for threshold in &mut self.thresholds {
+            threshold.val -= reference;
+        }
+    }
+    // Thresholds the current stats already reach are left out, like caps they're worth nothing to the build.
+    fn gain_between(&self, from: f64, to: f64) -> f64 {
+        return self.thresholds.iter().filter(|threshold| threshold.val > from && threshold.val <= to).map(|threshold| threshold.gain).sum();
+    }
+    pub fn calculate_gain(&self, new_val: f64) -> f64 {
+        return self.gain_between(0.0, new_val);
+    }
+    pub fn calculate_gain_incremental(&self, inc: f64, reference: f64) -> f64 {
+        return self.gain_between(reference, reference + inc);
+    }
+}
+
+impl GainFunction for RequirementBreakpoint {
+    fn gain(&self, stats: &CurStats) -> f64 {
+        return self.calculate_gain(self.value_of(stats));
+    }
+    fn gain_by_bonus(&self, bonus: &Bonus, reference: &CurStats) -> f64 {
+        return self.calculate_gain_incremental(contribution(self.stat, &self.sources, bonus.get_stat()) * bonus.get_val() as f64, self.value_of(reference));
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ThresholdSpec {
+    #[serde(flatten)]
+    value: UnitValue,
+    gain: f64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct BreakpointSpec {
+    stat: Stat,
+    thresholds: Vec<ThresholdSpec>,
+}
+
+impl TryFrom<BreakpointSpec> for RequirementBreakpoint {
+    type Error = String;
+    fn try_from(spec: BreakpointSpec) -> Result<Self, Self::Error> {
+        let mut thresholds = Vec::new();
+        for threshold in spec.thresholds {
+            let (val, units) = threshold.value.parse(spec.stat, "threshold")?;
+            thresholds.push(BreakpointThreshold{val, units, gain: threshold.gain});
+        }
+        return Self::new(spec.stat, thresholds).ok_or(String::from("a breakpoint requirement needs thresholds going up, given the same way, and no negative gains"));
+    }
+}
+
+impl From<RequirementBreakpoint> for BreakpointSpec {
+    fn from(breakpoint: RequirementBreakpoint) -> Self {
+        let thresholds = breakpoint.thresholds.iter().map(|threshold| ThresholdSpec{value: UnitValue::of(breakpoint.stat, threshold.val, threshold.units), gain: threshold.gain}).collect();
+        return Self{stat: breakpoint.stat, thresholds};
+    }
+}
+
+// A region of a piecewise requirement, every point of the stat below val (and above the previous region) is worth weight.
+#[derive(Clone)]
+pub struct PiecewiseRegion {
+    val: f64,
+    units: Option<f64>,
+    weight: f64,
+}
+
+impl PiecewiseRegion {
+    pub fn new(val: u32, weight: f64) -> Self {
+        return Self{val: val as f64, units: None, weight};
+    }
+    pub fn new_in_units(units: f64, weight: f64) -> Self {
+        return Self{val: 0.0, units: Some(units), weight};
+    }
+    pub fn get_val(&self) -> f64 { return self.val; }
+    pub fn get_units(&self) -> Option<f64> { return self.units; }
+    pub fn get_weight(&self) -> f64 { return self.weight; }
+}
+
+// Weights changing at breakpoints, like expertise up to the dodge cap and then up to the parry cap. Regions go up by val and
+// points past the last one are worth weight_after, nothing by default. Weights can't be negative since more stats never hurt in the search.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "PiecewiseSpec", into = "PiecewiseSpec")]
+pub struct RequirementPiecewise {
+    stat: Stat,
+    regions: Vec<PiecewiseRegion>,
+    weight_after: f64,
+    sources: Vec<(Stat, f64)>,
+}
+
+impl RequirementPiecewise {
+    // None without regions, with negative weights or with breakpoints not going up.
+    pub fn new(stat: Stat, regions: Vec<PiecewiseRegion>, weight_after: f64) -> Option<Self> {
+        if regions.is_empty() || weight_after < 0.0 || regions.iter().any(|region| region.weight < 0.0) { return None; }
+        let in_units = regions[0].units.is_some();
+        if in_units && RatingTable::unit_of(stat).is_none() { return None; }
+        if regions.iter().any(|region| region.units.is_some() != in_units) { return None; }
+        let bounds: Vec<f64> = regions.iter().map(|region| region.units.unwrap_or(region.val)).collect();
+        if bounds.windows(2).any(|pair| pair[0] >= pair[1]) { return None; }
+        return Some(Self{stat, regions, weight_after, sources: vec![]});
+    }
+    pub fn get_stat(&self) -> Stat { return self.stat; }
+    pub fn get_regions(&self) -> &[PiecewiseRegion] { return &self.regions; }
+    pub fn get_weight_after(&self) -> f64 { return self.weight_after; }
+    fn last_breakpoint(&self) -> f64 {
+        return self.regions.last().unwrap().val;
+    }
+    pub fn convert_units(&mut self, ratings: &RatingTable) -> () {
+        for region in &mut self.regions {
+            convert_units(self.stat, &mut region.val, region.units, ratings);
+        }
+    }
+    // Same as RequirementCap::lower for every breakpoint.
+    pub fn lower(&mut self, units: f64, ratings: &RatingTable) -> () {
+        for region in &mut self.regions {
+            lower(self.stat, &mut region.val, &mut region.units, units, ratings);
+        }
+    }
+    pub fn value_of(&self, stats: &CurStats) -> f64 {
+        return value_of(self.stat, &self.sources, stats);
+    }
+    pub fn make_incremental(&mut self, cur_stats: &CurStats) -> () {
+        let reference = self.value_of(cur_stats);
+        for region in &mut self.regions {
+            region.val -= reference;
+        }
+    }
+    // Sum of the weights of every point between from and to.
+    fn gain_between(&self, from: f64, to: f64) -> f64 {
+        let mut gain = 0.0;
+        let mut lower = f64::NEG_INFINITY;
+        for region in &self.regions {
+            gain += (to.min(region.val) - from.max(lower)).max(0.0) * region.weight;
+            lower = region.val;
+        }
+        return gain + (to - from.max(lower)).max(0.0) * self.weight_after;
+    }
+    pub fn calculate_gain(&self, new_val: f64) -> f64 {
+        return self.gain_between(0.0, new_val);
+    }
+    pub fn calculate_gain_incremental(&self, inc: f64, reference: f64) -> f64 {
+        return self.gain_between(reference, reference + inc);
+    }
+    // No point from reference on is worth more than the heaviest region still ahead.
+    fn slope_from(&self, reference: f64) -> f64 {
+        return self.regions.iter().filter(|region| region.val > reference).map(|region| region.weight).fold(self.weight_after, f64::max);
+    }
+}
+
+impl GainFunction for RequirementPiecewise {
+    fn gain(&self, stats: &CurStats) -> f64 {
+        return self.calculate_gain(self.value_of(stats));
+    }
+    fn gain_by_bonus(&self, bonus: &Bonus, reference: &CurStats) -> f64 {
+        return self.calculate_gain_incremental(contribution(self.stat, &self.sources, bonus.get_stat()) * bonus.get_val() as f64, self.value_of(reference));
+    }
+}
+
+// All regions have to be given the same way.
+#[derive(Clone, Serialize, Deserialize)]
+struct RegionSpec {
+    #[serde(flatten)]
+    value: UnitValue,
+    weight: f64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct PiecewiseSpec {
+    stat: Stat,
+    regions: Vec<RegionSpec>,
+    #[serde(default)]
+    weight_after: f64,
+}
+
+impl TryFrom<PiecewiseSpec> for RequirementPiecewise {
+    type Error = String;
+    fn try_from(spec: PiecewiseSpec) -> Result<Self, Self::Error> {
+        let mut regions = Vec::new();
+        for region in spec.regions {
+            let (val, units) = region.value.parse(spec.stat, "region")?;
+            regions.push(PiecewiseRegion{val, units, weight: region.weight});
+        }
+        return Self::new(spec.stat, regions, spec.weight_after).ok_or(String::from("a piecewise requirement needs regions going up, given the same way, and no negative weights"));
+    }
+}
+
+impl From<RequirementPiecewise> for PiecewiseSpec {
+    fn from(piecewise: RequirementPiecewise) -> Self {
+        let regions = piecewise.regions.iter().map(|region| RegionSpec{value: UnitValue::of(piecewise.stat, region.val, region.units), weight: region.weight}).collect();
+        return Self{stat: piecewise.stat, regions, weight_after: piecewise.weight_after};
+    }
+}
+
+// Avoidance from rating shrinking the closer it gets to cap, in percent (or skill) before and after: 1 / (1 / cap + k / before).
+// Weight is per point of rating the avoidance is worth after diminishing, so the first points count about weight / k.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "DiminishingSpec", into = "DiminishingSpec")]
+pub struct RequirementDiminishing {
+    stat: Stat,
+    k: f64,
+    cap: f64,
+    weight: f64,
+    sources: Vec<(Stat, f64)>,
+    rating_per_unit: f64,
+    // Value of the stat before the build's growth, all of it diminishes together but for the rating of undiminished units.
+    base: f64,
+    undiminished: f64,
+}
+
+impl RequirementDiminishing {
+    // None if the stat isn't a combat rating or the constants aren't positive.
+    pub fn new(stat: Stat, k: f64, cap: f64, weight: f64) -> Option<Self> {
+        if !(k > 0.0 && cap > 0.0 && weight >= 0.0) { return None; }
+        let rating_per_unit = RatingTable::default().rating_per_unit(stat)?;
+        return Some(Self{stat, k, cap, weight, sources: vec![], rating_per_unit, base: 0.0, undiminished: 0.0});
+    }
+    pub fn get_stat(&self) -> Stat { return self.stat; }
+    pub fn get_k(&self) -> f64 { return self.k; }
+    pub fn get_cap(&self) -> f64 { return self.cap; }
+    pub fn get_weight(&self) -> f64 { return self.weight; }
+    pub fn convert_units(&mut self, ratings: &RatingTable) -> () {
+        self.rating_per_unit = ratings.rating_per_unit(self.stat).expect("Diminishing returns are only made for ratings!");
+    }
+    // Leaves units the character gets from elsewhere, like a night elf's dodge, out of the curve. The current stats hold
+    // them as the rating they're worth. Must come before make_incremental.
+    pub fn lower(&mut self, units: f64, ratings: &RatingTable) -> () {
+        self.undiminished = ratings.to_rating(self.stat, units).unwrap_or(0) as f64;
+    }
+    pub fn value_of(&self, stats: &CurStats) -> f64 {
+        return value_of(self.stat, &self.sources, stats);
+    }
+    pub fn make_incremental(&mut self, cur_stats: &CurStats) -> () {
+        self.base = self.value_of(cur_stats) - self.undiminished;
+    }
+    // Rating worth of the avoidance after diminishing returns with rating on top of base.
+    fn diminished(&self, rating: f64) -> f64 {
+        let before = (self.base + rating).max(0.0) / self.rating_per_unit;
+        return before * self.cap / (before + self.k * self.cap) * self.rating_per_unit;
+    }
+    pub fn calculate_gain(&self, new_val: f64) -> f64 {
+        return (self.diminished(new_val) - self.diminished(0.0)) * self.weight;
+    }
+    pub fn calculate_gain_incremental(&self, inc: f64, reference: f64) -> f64 {
+        return (self.diminished(reference + inc) - self.diminished(reference)) * self.weight;
+    }
+    // The curve only gets flatter, so the slope at reference bounds every later point.
+    fn slope_from(&self, reference: f64) -> f64 {
+        let before = (self.base + reference).max(0.0) / self.rating_per_unit;
+        return self.k * self.cap * self.cap / (before + self.k * self.cap).powi(2) * self.weight;
+    }
+}
+
+impl GainFunction for RequirementDiminishing {
+    fn gain(&self, stats: &CurStats) -> f64 {
+        return self.calculate_gain(self.value_of(stats));
+    }
+    fn gain_by_bonus(&self, bonus: &Bonus, reference: &CurStats) -> f64 {
+        return self.calculate_gain_incremental(contribution(self.stat, &self.sources, bonus.get_stat()) * bonus.get_val() as f64, self.value_of(reference));
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct DiminishingSpec {
+    stat: Stat,
+    k: f64,
+    cap: f64,
+    weight: f64,
+}
+
+impl TryFrom<DiminishingSpec> for RequirementDiminishing {
+    type Error = String;
+    fn try_from(spec: DiminishingSpec) -> Result<Self, Self::Error> {
+        if RatingTable::unit_of(spec.stat).is_none() { return Err(format!("{} has no rating to diminish", spec.stat)); }
+        return Self::new(spec.stat, spec.k, spec.cap, spec.weight).ok_or(String::from("diminishing returns need a positive k and cap and no negative weight"));
+    }
+}
+
+impl From<RequirementDiminishing> for DiminishingSpec {
+    fn from(diminishing: RequirementDiminishing) -> Self {
+        return Self{stat: diminishing.stat, k: diminishing.k, cap: diminishing.cap, weight: diminishing.weight};
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct RequirementWeighted {
     stat: Stat,
     weight: f64,
+    #[serde(skip)]
+    sources: Vec<(Stat, f64)>,
 }
 
 impl RequirementWeighted {
     pub fn new(stat: Stat, weight: f64) -> Self {
-        return Self{stat, weight};
+        return Self{stat, weight, sources: vec![]};
     }
     pub fn get_stat(&self) -> Stat { return self.stat; }
     pub fn get_weight(&self) -> f64 { return self.weight; }
-    pub fn calculate_gain(&self, new_val: u32) -> f64 {
-        return new_val as f64 * self.weight;
+    pub fn value_of(&self, stats: &CurStats) -> f64 {
+        return value_of(self.stat, &self.sources, stats);
+    }
+    pub fn calculate_gain(&self, new_val: f64) -> f64 {
+        return new_val * self.weight;
+    }
+}
+
+impl GainFunction for RequirementWeighted {
+    fn gain(&self, stats: &CurStats) -> f64 {
+        return self.calculate_gain(self.value_of(stats));
+    }
+    fn gain_by_bonus(&self, bonus: &Bonus, _reference: &CurStats) -> f64 {
+        return self.calculate_gain(contribution(self.stat, &self.sources, bonus.get_stat()) * bonus.get_val() as f64);
     }
 }
\ No newline at end of file
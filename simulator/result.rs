@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use serde::Serialize;
+use crate::char::CurStats;
+use crate::items::{Color, ConsumableCategory, Item};
+use crate::ratings::RatingTable;
+use crate::simulator::distribution::Distribution;
+use crate::{ItemSlot, Stat};
+
+#[derive(Clone, Serialize)]
+pub struct SocketResult {
+    color: Color,
+    gem: Option<String>,
+    matches: bool,
+    active: bool,
+}
+
+impl SocketResult {
+    pub fn get_color(&self) -> Color { return self.color; }
+    pub fn get_gem(&self) -> Option<&str> { return self.gem.as_deref(); }
+    pub fn does_match(&self) -> bool { return self.matches; }
+    // False only for a meta gem whose activation rules the build doesn't meet.
+    pub fn is_active(&self) -> bool { return self.active; }
+}
+
+#[derive(Clone, Serialize)]
+pub struct ItemResult {
+    slot: ItemSlot,
+    name: String,
+    enchant: Option<String>,
+    sockets: Vec<SocketResult>,
+    socket_bonus: Option<String>,
+    socket_bonus_active: bool,
+}
+
+impl ItemResult {
+    fn new(item: &Item, items: &[Item]) -> Self {
+        let sockets = item.get_sockets().iter().map(|socket| SocketResult{
+            color: socket.get_color(),
+            gem: socket.get_gem().as_ref().map(|gem| gem.get_name().to_owned()),
+            matches: socket.does_match(),
+            active: socket.get_gem().as_ref().is_none_or(|gem| !gem.is_meta() || gem.is_active(items)),
+        }).collect();
+        let enchant = item.get_enchantment().as_ref().map(|enchant| enchant.get_name().to_owned());
+        let socket_bonus = item.get_socket_bonus().as_ref().map(|bonus| bonus.to_string());
+        return Self{slot: item.get_slot(), name: item.get_name().to_owned(), enchant, sockets, socket_bonus, socket_bonus_active: item.is_socket_bonus_active()};
+    }
+    pub fn get_slot(&self) -> ItemSlot { return self.slot; }
+    pub fn get_name(&self) -> &str { return &self.name; }
+    pub fn get_enchant(&self) -> Option<&str> { return self.enchant.as_deref(); }
+    pub fn get_sockets(&self) -> &[SocketResult] { return &self.sockets; }
+    pub fn get_socket_bonus(&self) -> Option<&str> { return self.socket_bonus.as_deref(); }
+    // Whether the gems were chosen to match colors for the bonus or the bonus was given up for stronger gems.
+    pub fn is_socket_bonus_active(&self) -> bool { return self.socket_bonus_active; }
+}
+
+#[derive(Clone, Serialize)]
+pub struct ConsumableResult {
+    name: String,
+    category: ConsumableCategory,
+}
+
+impl ConsumableResult {
+    pub fn get_name(&self) -> &str { return &self.name; }
+    pub fn get_category(&self) -> ConsumableCategory { return self.category; }
+}
+
+#[derive(Clone, Serialize)]
+pub struct OptimizationResult {
+    items: Vec<ItemResult>,
+    consumables: Vec<ConsumableResult>,
+    stat_growth: CurStats,
+    // Percent, or skill for expertise, of every combat rating in stat_growth.
+    converted_stats: HashMap<Stat, f64>,
+    gain: f64,
+}
+
+impl OptimizationResult {
+    pub(crate) fn new(distr: &Distribution, ratings: &RatingTable) -> Self {
+        let converted_stats = distr.get_stat_growth().iter_stats().filter_map(|(stat, val)| ratings.to_units(*stat, *val).map(|units| (*stat, units))).collect();
+        return Self{
+            items: distr.get_items().iter().map(|item| ItemResult::new(item, distr.get_items())).collect(),
+            consumables: distr.get_consumables().iter().map(|consumable| ConsumableResult{name: consumable.get_name().to_owned(), category: consumable.get_category()}).collect(),
+            stat_growth: distr.get_stat_growth().clone(),
+            converted_stats,
+            gain: distr.get_gain(),
+        };
+    }
+    pub fn get_items(&self) -> &[ItemResult] { return &self.items; }
+    pub fn get_consumables(&self) -> &[ConsumableResult] { return &self.consumables; }
+    pub fn get_stat_growth(&self) -> &CurStats { return &self.stat_growth; }
+    pub fn get_converted_stat(&self, stat: Stat) -> Option<f64> { return self.converted_stats.get(&stat).copied(); }
+    pub fn get_gain(&self) -> f64 { return self.gain; }
+    pub fn to_json(&self) -> String {
+        return serde_json::to_string_pretty(self).expect("Results always serialize to JSON!");
+    }
+    // One row per socket; items without sockets still get a row so their enchantment isn't lost.
+    // After a blank line a second table sums up the build: the gain, every stat grown with its percent or skill if it's a rating,
+    // and every consumable with its category.
+    pub fn to_csv(&self) -> String {
+        let mut res = String::from("slot,item,enchant,socket_bonus_active,socket_color,gem,matches,active\n");
+        for item in &self.items {
+            let prefix = format!("{},{},{},{}", item.slot, csv_field(&item.name), csv_field(item.get_enchant().unwrap_or("")), item.socket_bonus_active);
+            if item.sockets.is_empty() {
+                res += &format!("{},,,,\n", prefix);
+            }
+            for socket in &item.sockets {
+                res += &format!("{},{},{},{},{}\n", prefix, socket.color, csv_field(socket.get_gem().unwrap_or("")), socket.matches, socket.active);
+            }
+        }
+        res += "\nsummary,name,value,converted\n";
+        res += &format!("gain,,{},\n", self.gain);
+        let mut grown: Vec<(&Stat, &u32)> = self.stat_growth.iter_stats().filter(|(_, val)| **val > 0).collect();
+        grown.sort_by_key(|(stat, _)| stat.to_string());
+        for (stat, val) in grown {
+            let converted = self.get_converted_stat(*stat).map_or(String::new(), |units| units.to_string());
+            res += &format!("stat,{},{},{}\n", stat, val, converted);
+        }
+        for consumable in &self.consumables {
+            res += &format!("consumable,{},{},\n", csv_field(&consumable.name), consumable.category);
+        }
+        return res;
+    }
+}
+
+fn csv_field(val: &str) -> String {
+    if val.contains([',', '"', '\n']) {
+        return format!("\"{}\"", val.replace('"', "\"\""));
+    }
+    return val.to_owned();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::{Consumable, Gem, GemSocket};
+    use crate::{Bonus, Bonuses, Requirement, RequirementWeighted};
+
+    fn sample() -> OptimizationResult {
+        let mut item = Item::new(String::from("feet, heroic"), ItemSlot::Feet, Bonuses::new(vec![]), vec![GemSocket::new(Color::Yellow), GemSocket::new(Color::Blue)], None, None);
+        item.get_socket_mut(0).set_gem(&Gem::new(vec![Color::Yellow], Bonuses::new(vec![Bonus::new(Stat::HitRate, 20)]), String::from("rigid")));
+        let ring = Item::new(String::from("ring"), ItemSlot::Ring1, Bonuses::new(vec![]), vec![], None, None);
+        let mut growth = CurStats::new();
+        growth.set_stat(Stat::HitRate, 20);
+        growth.set_stat(Stat::Agility, 10);
+        let reqs = vec![Requirement::RequirementWeighted(RequirementWeighted::new(Stat::HitRate, 2.0))];
+        let mut distr = Distribution::new(growth, &reqs, &[item, ring]);
+        distr.set_consumables(vec![Consumable::new(String::from("fish, spicy"), ConsumableCategory::Food, Bonuses::new(vec![Bonus::new(Stat::Agility, 10)]))]);
+        return OptimizationResult::new(&distr, &RatingTable::for_level(70).unwrap());
+    }
+    #[test]
+    fn csv_rows() {
+        let csv = sample().to_csv();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows, vec!["slot,item,enchant,socket_bonus_active,socket_color,gem,matches,active", "Feet,\"feet, heroic\",,false,Yellow,rigid,true,true", "Feet,\"feet, heroic\",,false,Blue,,false,true", "Ring1,ring,,false,,,,",
+            "", "summary,name,value,converted", "gain,,40,", "stat,Agility,10,", &format!("stat,HitRate,20,{}", 20.0 * 52.0 / 820.0), "consumable,\"fish, spicy\",Food,"]);
+    }
+    #[test]
+    fn json_fields() {
+        let json: serde_json::Value = serde_json::from_str(&sample().to_json()).unwrap();
+        assert_eq!(json["gain"], 40.0);
+        assert_eq!(json["stat_growth"]["HitRate"], 20);
+        assert_eq!(json["converted_stats"]["HitRate"].as_f64().unwrap(), 20.0 * 52.0 / 820.0);
+        assert_eq!(json["items"][0]["sockets"][0]["gem"], "rigid");
+        assert_eq!(json["consumables"][0]["category"], "Food");
+    }
+}
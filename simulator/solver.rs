@@ -0,0 +1,666 @@
+use std::collections::HashMap;
+use crate::char::CurStats;
+use crate::items::{count_gem_colors, Color, Gem, GemLimit, Item, MetaActivation};
+use crate::optimizer::BestBonusFinder;
+use crate::simulator::distribution::Distribution;
+use crate::simulator::requirements::{shares, GainFunction, Requirement};
+use crate::{Bonus, Bonuses};
+
+// Gains closer than this are equal: a build must beat the best one by more to replace it, and so must the bound of a branch to be searched.
+const EPSILON: f64 = 1e-6;
+// The colors meta gems count.
+const COLORS: [Color; 3] = [Color::Red, Color::Yellow, Color::Blue];
+
+enum Decision {
+    Enchant(usize),
+    // An empty socket. The sockets of a group are interchangeable and take gems in non-decreasing order of their list, so no
+    // permutation is searched twice: same-colored sockets of an item chasing its socket bonus, or all the free ones.
+    // The last socket of such an item settles the bonus.
+    Socket { item: usize, socket: usize, meta: bool, group: usize, chasing: bool, settles: bool },
+}
+
+// An item chasing its socket bonus and its empty sockets, each with whether it's a meta socket and its color, in that order.
+type Chasing = (usize, Vec<(bool, usize, usize)>);
+
+// What the decisions from a state on can add to its gain. Exact once it was more than the search needed from there,
+// otherwise only known not to be more than this.
+#[derive(Clone, Copy)]
+struct Future {
+    gain: f64,
+    exact: bool,
+    // The option giving it when exact, None for a socket no gem can go into.
+    choice: Option<usize>,
+}
+
+// Exact branch and bound over gems and enchantments of one variant of the build. What the decisions from a state on can add
+// only depends on its state_key, so every state is searched once.
+pub(crate) struct Solver<'a> {
+    reqs: &'a [Requirement],
+    optimizer: &'a BestBonusFinder,
+    items: Vec<Item>,
+    decisions: Vec<Decision>,
+    gems: Vec<Gem>,
+    metas: Vec<Gem>,
+    // How many of each gem the search has placed, metas after the others.
+    placed: Vec<u32>,
+    // Every limit with the name or category it counts, the limit of each gem if it has one and how many more gems each lets in.
+    limits: Vec<(GemLimit, String)>,
+    limit_of: Vec<Option<usize>>,
+    room: Vec<u32>,
+    // Gems of every color in the items, and for each item gems in its colored sockets not matching them.
+    colors: [u32; 3],
+    unmatched: Vec<u32>,
+    // The option taken at every decision so far, and which decisions are meta sockets.
+    chosen: Vec<Option<usize>>,
+    meta_sockets: Vec<usize>,
+    // How many gems of every color still matter to activating a meta gem that adds anything, 0 for none.
+    color_caps: [u32; 3],
+    bound: Bound,
+    futures: HashMap<Vec<u64>, Future>,
+}
+
+impl<'a> Solver<'a> {
+    // Enchantments are decided before any gem with prechant, after every one without. The best build is the same either way.
+    pub(crate) fn new(items: Vec<Item>, reqs: &'a [Requirement], optimizer: &'a BestBonusFinder, try_gems: bool, try_chanting: bool, prechant: bool) -> Self {
+        // A single gem can't break a limit, so this only leaves out gems of professions the character doesn't have.
+        let (metas, gems): (Vec<Gem>, Vec<Gem>) = optimizer.get_gems().iter().filter(|gem| optimizer.is_gem_allowed(gem, &[])).cloned().partition(|gem| gem.is_meta());
+        let gems = without_dominated(gems, &metas, &items, reqs);
+        let mut decisions = Vec::new();
+        let mut enchants = Vec::new();
+        for (item_ind, item) in items.iter().enumerate() {
+            if try_chanting && !item.is_enchanted() && !optimizer.get_enchants_for_slot(item.get_slot()).is_empty() {
+                enchants.push(Decision::Enchant(item_ind));
+            }
+        }
+        if prechant { decisions.append(&mut enchants); }
+        // Items chasing their socket bonus one after another, the ones with meta sockets last so meta gems are placed once the
+        // colors are known. Free sockets in between, free meta sockets at the very end.
+        let mut chasing: [Vec<Chasing>; 2] = [Vec::new(), Vec::new()];
+        let mut free: [Vec<(usize, usize)>; 2] = [Vec::new(), Vec::new()];
+        for (item_ind, item) in items.iter().enumerate().filter(|_| try_gems) {
+            let empty: Vec<usize> = (0..item.get_sockets().len()).filter(|&ind| item.get_sockets()[ind].is_empty()).collect();
+            let color_of = |socket: usize| -> Color { return item.get_sockets()[socket].get_color(); };
+            // Prismatic sockets never decide the socket bonus.
+            let chases = item.get_socket_bonus().is_some() && !item.is_socket_bonus_active() && empty.iter().any(|&ind| color_of(ind) != Color::Prismatic);
+            let mut sockets = Vec::new();
+            for socket in empty {
+                match color_of(socket) {
+                    Color::Prismatic => free[0].push((item_ind, socket)),
+                    color if chases => sockets.push((color == Color::Meta, color as usize, socket)),
+                    Color::Meta => free[1].push((item_ind, socket)),
+                    _ => free[0].push((item_ind, socket)),
+                }
+            }
+            sockets.sort();
+            let late = sockets.last().is_some_and(|&(meta, _, _)| meta);
+            if !sockets.is_empty() { chasing[late as usize].push((item_ind, sockets)); }
+        }
+        let mut groups = 0;
+        for late in [false, true] {
+            for (item, sockets) in &chasing[late as usize] {
+                for (pos, &(meta, color, socket)) in sockets.iter().enumerate() {
+                    if pos == 0 || sockets[pos - 1].1 != color { groups += 1; }
+                    decisions.push(Decision::Socket{item: *item, socket, meta, group: groups, chasing: true, settles: pos + 1 == sockets.len()});
+                }
+            }
+            groups += 1;
+            for &(item, socket) in &free[late as usize] {
+                decisions.push(Decision::Socket{item, socket, meta: late, group: groups, chasing: false, settles: false});
+            }
+        }
+        decisions.append(&mut enchants);
+        let placed = vec![0; gems.len() + metas.len()];
+        let mut limits: Vec<(GemLimit, String)> = Vec::new();
+        let limit_of = gems.iter().chain(&metas).map(|gem| gem.get_limit().as_ref().map(|limit| {
+            let key = match limit {
+                GemLimit::UniqueEquipped => gem.get_name(),
+                GemLimit::Category { category, .. } => category.as_str(),
+            };
+            return limits.iter().position(|(other, other_key)| other == limit && other_key == key).unwrap_or_else(|| {
+                limits.push((limit.clone(), key.to_string()));
+                return limits.len() - 1;
+            });
+        })).collect();
+        let room = limits.iter().map(|(limit, key)| room(limit, key, &items)).collect();
+        let colors = COLORS.map(|color| count_gem_colors(&items, color));
+        let unmatched = items.iter().map(|item| item.get_sockets().iter().filter(|socket| !socket.is_empty() && socket.get_color() != Color::Prismatic && !socket.does_match()).count() as u32).collect();
+        let chosen = vec![None; decisions.len()];
+        let meta_sockets = (0..decisions.len()).filter(|&ind| matches!(decisions[ind], Decision::Socket { meta: true, .. })).collect();
+        let with_metas = items.iter().flat_map(|item| item.get_sockets().iter()).any(|socket| socket.get_color() == Color::Meta);
+        let in_place = items.iter().flat_map(|item| item.get_sockets().iter()).filter_map(|socket| socket.get_gem().as_ref());
+        let mut color_caps = [0; 3];
+        for rule in metas.iter().chain(in_place).filter(|meta| with_metas && shares(reqs, meta.get_bonuses()).iter().any(|&share| share != 0.0)).flat_map(|meta| meta.get_activation()) {
+            for (ind, color) in COLORS.iter().enumerate() {
+                color_caps[ind] = color_caps[ind].max(match *rule {
+                    MetaActivation::AtLeast { color: needed, count } => if needed == *color { count } else { 0 },
+                    MetaActivation::MoreThan { more, less } => if more == *color || less == *color { u32::MAX } else { 0 },
+                });
+            }
+        }
+        return Self{reqs, optimizer, items, decisions, gems, metas, placed, limits, limit_of, room, colors, unmatched, chosen, meta_sockets, color_caps, bound: Bound::default(), futures: HashMap::new()};
+    }
+    // The best build gaining more than floor, or the best one at all without a floor.
+    pub(crate) fn solve(mut self, cur_stats: CurStats, floor: Option<f64>) -> Option<Distribution> {
+        let gain = cur_stats.calculate_gain(self.reqs);
+        let mut res = None;
+        let need = match floor {
+            Some(floor) => floor - gain,
+            None => {
+                let distr = Distribution::new(cur_stats.clone(), self.reqs, &self.items);
+                if self.reqs.iter().all(|req| req.shortfall(distr.get_stat_growth()) <= EPSILON) {
+                    let need = distr.get_gain() - gain;
+                    res = Some(distr);
+                    need
+                } else { f64::NEG_INFINITY }
+            },
+        };
+        self.bound = Bound::new(&self, &cur_stats);
+        if !self.future(0, &cur_stats, need + EPSILON).1 { return res; }
+        // Every state on the way to the best build is known exactly, along with the option leading on.
+        let mut stats = cur_stats;
+        for depth in 0..self.decisions.len() {
+            let choice = self.futures[&self.state_key(depth, &stats)].choice;
+            self.place(depth, choice);
+            self.add_choice(depth, choice, &mut stats);
+        }
+        for depth in 0..self.decisions.len() {
+            let Some(ind) = self.chosen[depth] else { continue; };
+            match self.decisions[depth] {
+                Decision::Enchant(item) => {
+                    let enchant = &self.optimizer.get_enchants_for_slot(self.items[item].get_slot())[ind];
+                    self.items[item].set_enchantment(enchant);
+                },
+                Decision::Socket { item, socket, meta, .. } => self.items[item].get_socket_mut(socket).set_gem(if meta { &self.metas[ind] } else { &self.gems[ind] }),
+            }
+        }
+        return Some(Distribution::new(stats, self.reqs, &self.items));
+    }
+    // The most the decisions from depth on add to the gain of stats and whether that's known exactly for being more than need,
+    // otherwise it's only known not to be more. Options go from the highest estimate down, so once one can't beat what's
+    // needed neither can the rest.
+    fn future(&mut self, depth: usize, stats: &CurStats, need: f64) -> (f64, bool) {
+        if depth == self.decisions.len() {
+            let res = self.finish(stats);
+            return (res, res > need);
+        }
+        let key = self.state_key(depth, stats);
+        if let Some(known) = self.futures.get(&key) {
+            if known.exact || known.gain <= need { return (known.gain, known.exact && known.gain > need); }
+        }
+        let can_match = self.can_match_at(depth);
+        // Meta gems the search placed only count in finish.
+        let metas: Vec<usize> = self.meta_sockets.iter().take_while(|&&ind| ind < depth).filter_map(|&ind| self.chosen[ind]).collect();
+        let (gain, rests) = self.bound.rests(self.reqs, depth, stats, &metas);
+        let bound = rests.as_ref().map_or(f64::NEG_INFINITY, |rests| self.bound.at(depth, can_match, &self.room, None, rests));
+        if bound <= need {
+            self.futures.insert(key, Future{gain: bound, exact: false, choice: None});
+            return (bound, false);
+        }
+        let rests = rests.unwrap();
+        let mut options: Vec<(Option<usize>, f64)> = self.options(depth).into_iter().map(|option| (option, self.estimate(depth, option, can_match, &rests))).collect();
+        // On equal estimates the option listed later goes first.
+        options.reverse();
+        options.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let mut best: Option<Future> = None;
+        let mut upper = f64::NEG_INFINITY;
+        for (option, ahead) in options {
+            let target = best.map_or(need, |best| need.max(best.gain + EPSILON));
+            if ahead <= target {
+                upper = upper.max(ahead);
+                break;
+            }
+            self.place(depth, option);
+            let mut next = stats.clone();
+            self.add_choice(depth, option, &mut next);
+            let step = next.calculate_gain(self.reqs) - gain;
+            let (res, beats) = self.future(depth + 1, &next, target - step);
+            self.take_back(depth, option);
+            upper = upper.max(step + res);
+            if beats { best = Some(Future{gain: step + res, exact: true, choice: option}); }
+        }
+        // Every option either gave the best or was cut for not beating it, or for not beating need at all.
+        let known = best.unwrap_or(Future{gain: upper, exact: false, choice: None});
+        self.futures.insert(key, known);
+        return (known.gain, known.exact);
+    }
+    // What the meta gems add once the whole build is known, if it reaches every minimum. The same as apply_active_metas, which
+    // would need the gems in the items.
+    fn finish(&self, stats: &CurStats) -> f64 {
+        let mut total = stats.clone();
+        let count = |color: Color| -> u32 { return COLORS.iter().position(|&other| other == color).map_or(0, |ind| self.colors[ind]); };
+        let in_place = self.items.iter().flat_map(|item| item.get_sockets().iter()).filter_map(|socket| socket.get_gem().as_ref()).filter(|gem| gem.is_meta());
+        for meta in in_place.chain(self.meta_sockets.iter().filter_map(|&depth| self.chosen[depth].map(|ind| &self.metas[ind]))) {
+            if meta.get_activation().iter().all(|rule| rule.missing_color_by(count).is_none()) { meta.get_bonuses().apply_bonuses(&mut total); }
+        }
+        if self.reqs.iter().any(|req| req.shortfall(&total) > EPSILON) { return f64::NEG_INFINITY; }
+        return total.calculate_gain(self.reqs) - stats.calculate_gain(self.reqs);
+    }
+    // Indices of the enchantments or gems the decision can take, gems not before the one the group placed last. Limits only get
+    // stricter with more gems, so a socket nothing fits into stays empty, and so does the rest of its group.
+    fn options(&self, depth: usize) -> Vec<Option<usize>> {
+        match self.decisions[depth] {
+            Decision::Enchant(item) => return (0..self.optimizer.get_enchants_for_slot(self.items[item].get_slot()).len()).map(Some).collect(),
+            Decision::Socket { meta, .. } => {
+                let pool = if meta { &self.metas } else { &self.gems };
+                let from = match self.previous(depth) {
+                    Some(Some(ind)) => ind,
+                    Some(None) => pool.len(),
+                    None => 0,
+                };
+                let offset = if meta { self.gems.len() } else { 0 };
+                let res: Vec<Option<usize>> = (from..pool.len()).filter(|&ind| self.limit_of[offset + ind].is_none_or(|limit| self.room[limit] > 0)).map(Some).collect();
+                if res.is_empty() { return vec![None]; }
+                return res;
+            },
+        }
+    }
+    // The option taken by the socket before this one in its group, None for the first socket of a group.
+    fn previous(&self, depth: usize) -> Option<Option<usize>> {
+        let group_of = |depth: usize| -> Option<usize> {
+            return match self.decisions[depth] {
+                Decision::Socket { group, .. } => Some(group),
+                Decision::Enchant(_) => None,
+            };
+        };
+        if depth == 0 || group_of(depth).is_none() || group_of(depth) != group_of(depth - 1) { return None; }
+        return Some(self.chosen[depth - 1]);
+    }
+    // The search only counts what it places, the items get their gems and enchantments once the best build is known.
+    fn place(&mut self, depth: usize, choice: Option<usize>) -> () {
+        self.chosen[depth] = choice;
+        self.count(depth, choice, true);
+    }
+    fn take_back(&mut self, depth: usize, choice: Option<usize>) -> () {
+        self.chosen[depth] = None;
+        self.count(depth, choice, false);
+    }
+    fn count(&mut self, depth: usize, choice: Option<usize>, placing: bool) -> () {
+        let Decision::Socket { item, socket, meta, chasing, .. } = self.decisions[depth] else { return; };
+        let step = |val: &mut u32| if placing { *val += 1 } else { *val -= 1 };
+        let gem = choice.map(|ind| if meta { &self.metas[ind] } else { &self.gems[ind] });
+        // A socket left empty doesn't match either.
+        if chasing && !gem.is_some_and(|gem| gem.get_colors().contains(&self.items[item].get_sockets()[socket].get_color())) { step(&mut self.unmatched[item]); }
+        let (Some(gem), Some(ind)) = (gem, choice) else { return; };
+        for (color, count) in COLORS.iter().zip(self.colors.iter_mut()) {
+            if !gem.is_meta() && gem.get_colors().contains(color) { step(count); }
+        }
+        let row = if meta { self.gems.len() + ind } else { ind };
+        step(&mut self.placed[row]);
+        if let Some(limit) = self.limit_of[row] {
+            if placing { self.room[limit] -= 1; } else { self.room[limit] += 1; }
+        }
+    }
+    // The stats a placed choice adds, with the socket bonus of the item if it settles it. Meta gems only count once the whole
+    // build is known, see finish.
+    fn add_choice(&self, depth: usize, choice: Option<usize>, stats: &mut CurStats) -> () {
+        match self.decisions[depth] {
+            Decision::Enchant(item) => {
+                if let Some(ind) = choice { self.optimizer.get_enchants_for_slot(self.items[item].get_slot())[ind].get_bonuses().apply_bonuses(stats); }
+            },
+            Decision::Socket { item, meta, settles, .. } => {
+                if let (Some(ind), false) = (choice, meta) { self.gems[ind].get_bonuses().apply_bonuses(stats); }
+                if settles && self.unmatched[item] == 0 { self.items[item].apply_socket_bonus(stats); }
+            },
+        }
+    }
+    // What the decisions from depth on can still add depends on where the requirements stand, the gems counting towards limits and
+    // meta activation, whether the item in progress can still match and where its group goes on from. Builds differing in anything
+    // else share it. Every entry is a count or value of its own, so none can overflow into another.
+    fn state_key(&self, depth: usize, stats: &CurStats) -> Vec<u64> {
+        let mut res = vec![depth as u64];
+        for req in self.reqs {
+            req.future_state(stats, &mut res);
+        }
+        res.extend(self.room.iter().map(|&room| u64::from(room)));
+        res.extend(self.placed[self.gems.len()..].iter().map(|&placed| u64::from(placed)));
+        for (count, cap) in self.colors.iter().zip(self.color_caps).filter(|(_, cap)| *cap > 0) {
+            res.push(u64::from(*count.min(&cap)));
+        }
+        if let Decision::Socket { chasing: true, .. } = self.decisions[depth] {
+            res.push(self.can_match_at(depth) as u64);
+        }
+        if let Some(previous) = self.previous(depth) {
+            res.push(previous.map_or(u64::MAX, |ind| ind as u64));
+        }
+        return res;
+    }
+    // Whether the item of a socket chasing its bonus still matches every socket filled so far.
+    fn can_match_at(&self, depth: usize) -> bool {
+        let Some(&Decision::Socket { item, chasing: true, .. }) = self.decisions.get(depth) else { return false; };
+        return self.unmatched[item] == 0;
+    }
+    // Most the option adds with what the decisions after it can, see Bound::estimate, without placing it.
+    fn estimate(&self, depth: usize, option: Option<usize>, can_match: bool, rests: &[f64]) -> f64 {
+        let (mut bonus, mut next_match) = (false, self.can_match_at(depth + 1));
+        if let Decision::Socket { item, socket, meta, chasing: true, settles, .. } = self.decisions[depth] {
+            let gem = option.map(|ind| if meta { &self.metas[ind] } else { &self.gems[ind] });
+            let matches = can_match && gem.is_some_and(|gem| gem.get_colors().contains(&self.items[item].get_sockets()[socket].get_color()));
+            if settles { bonus = matches; } else { next_match = matches; }
+        }
+        return self.bound.estimate(depth, option, bonus, next_match, &self.room, rests);
+    }
+}
+
+// A gem is never better than an unlimited one with at least its share of every requirement and at least its colors, unless
+// some meta gem wants fewer gems of a color. Of equal gems the one listed later stays, as it would win ties anyway.
+fn without_dominated(gems: Vec<Gem>, metas: &[Gem], items: &[Item], reqs: &[Requirement]) -> Vec<Gem> {
+    let placed_metas = items.iter().flat_map(|item| item.get_sockets().iter()).filter_map(|socket| socket.get_gem().as_ref());
+    let more_colors_hurt = metas.iter().chain(placed_metas).flat_map(|meta| meta.get_activation().iter()).any(|rule| matches!(rule, MetaActivation::MoreThan { .. }));
+    let shares: Vec<Vec<f64>> = gems.iter().map(|gem| shares(reqs, gem.get_bonuses())).collect();
+    let dominates = |better: usize, worse: usize| -> bool {
+        let (better_colors, worse_colors) = (gems[better].get_colors(), gems[worse].get_colors());
+        if gems[better].get_limit().is_some() || !worse_colors.iter().all(|color| better_colors.contains(color)) { return false; }
+        if more_colors_hurt && better_colors.len() != worse_colors.len() { return false; }
+        if shares[better].iter().zip(&shares[worse]).any(|(better_share, worse_share)| better_share < worse_share) { return false; }
+        return shares[better] != shares[worse] || better_colors.len() != worse_colors.len() || better > worse;
+    };
+    return (0..gems.len()).filter(|&worse| !(0..gems.len()).any(|better| better != worse && dominates(better, worse))).map(|ind| gems[ind].clone()).collect();
+}
+
+// How many more gems the limit lets the items take.
+fn room(limit: &GemLimit, key: &str, items: &[Item]) -> u32 {
+    let gems = items.iter().flat_map(|item| item.get_sockets().iter()).filter_map(|socket| socket.get_gem().as_ref());
+    return match limit {
+        GemLimit::UniqueEquipped => if gems.into_iter().any(|gem| gem.get_name() == key) { 0 } else { 1 },
+        GemLimit::Category { count, .. } => {
+            count.saturating_sub(gems.filter(|gem| matches!(gem.get_limit(), Some(GemLimit::Category { category, .. }) if category == key)).count() as u32)
+        },
+    };
+}
+
+// The decisions from a depth on add at most their options one by one. A requirement with a slope bound gains at most
+// share * slope * what the options add + (1 - share) * what the most of every stat they can add gains, for any share
+// between 0 and 1, so that first part goes to the options. Weighted requirements go there entirely, requirements without
+// a slope bound not at all. An item chasing its socket bonus either matches and gets it, or not. Gems with a limit only
+// count as many times as the limit has room, for what they add over the best gem without one.
+#[derive(Default)]
+struct Bound {
+    // What every option adds to each requirement at its slope bound, see Requirement::rate_bound. Metas are rows from gems on.
+    rows: Vec<Vec<f64>>,
+    metas: usize,
+    meta_bonuses: Vec<Bonuses>,
+    // The limit of every row if it has one, see Solver::limits.
+    limited: Vec<Option<usize>>,
+    limits: usize,
+    // For every decision the rows of its options, the ones matching the socket's color for sockets of an item chasing its
+    // socket bonus with the row of the bonus itself at the last one, and where the item ends.
+    options: Vec<Vec<usize>>,
+    matching: Vec<Option<Vec<usize>>>,
+    bonus: Vec<Option<usize>>,
+    ends: Vec<usize>,
+    // The most of every stat the decisions from each depth on add, then what meta gems placed before the search add to everything.
+    reach: Vec<CurStats>,
+    placed: Vec<usize>,
+    // One table with shares tuned to the start of the search, one with none past the fixed ones.
+    tables: Vec<Table>,
+}
+
+// For some shares of the requirements what every row adds and for each depth what the options from there add in the best
+// case: the item in progress without and with its socket bonus, everything after it and the most one more gem of each
+// limit adds.
+struct Table {
+    shares: Vec<f64>,
+    values: Vec<f64>,
+    without: Vec<f64>,
+    with: Vec<f64>,
+    after: Vec<f64>,
+    extra: Vec<Vec<f64>>,
+}
+
+impl Bound {
+    fn new(solver: &Solver, cur_stats: &CurStats) -> Self {
+        let reqs = solver.reqs;
+        let mut res = Self::default();
+        let add_row = |rows: &mut Vec<Vec<f64>>, bonuses: &mut dyn Iterator<Item = &Bonus>| -> usize {
+            let mut row = vec![0.0; reqs.len()];
+            for bonus in bonuses.filter(|bonus| bonus.get_val() > 0) {
+                for (ind, req) in reqs.iter().enumerate() {
+                    row[ind] += req.rate_bound(bonus.get_stat(), cur_stats) * bonus.get_val() as f64;
+                }
+            }
+            rows.push(row);
+            return rows.len() - 1;
+        };
+        for gem in solver.gems.iter().chain(&solver.metas) {
+            add_row(&mut res.rows, &mut gem.get_bonuses().iter());
+        }
+        res.limited = solver.limit_of.clone();
+        res.limits = solver.limits.len();
+        res.metas = solver.gems.len();
+        res.meta_bonuses = solver.metas.iter().map(|meta| meta.get_bonuses().clone()).collect();
+        let most = |stats: &mut CurStats, options: &mut dyn Iterator<Item = &Bonus>| -> () {
+            let mut best = CurStats::new();
+            for bonus in options {
+                if bonus.get_val() > best.get_stat_val(bonus.get_stat()) { best.set_stat(bonus.get_stat(), bonus.get_val()); }
+            }
+            *stats = stats.sum_of(&best);
+        };
+        let mut reach = vec![CurStats::new(); solver.decisions.len() + 1];
+        let mut end = solver.decisions.len();
+        for (depth, decision) in solver.decisions.iter().enumerate().rev() {
+            let mut stats = reach[depth + 1].clone();
+            match *decision {
+                Decision::Enchant(item) => {
+                    let enchants = solver.optimizer.get_enchants_for_slot(solver.items[item].get_slot());
+                    res.options.push(enchants.iter().map(|enchant| add_row(&mut res.rows, &mut enchant.get_bonuses().iter())).collect());
+                    res.matching.push(None);
+                    res.bonus.push(None);
+                    most(&mut stats, &mut enchants.iter().flat_map(|enchant| enchant.get_bonuses().iter()));
+                },
+                Decision::Socket { item, socket, meta, chasing, settles, .. } => {
+                    let pool = if meta { res.metas..res.metas + solver.metas.len() } else { 0..res.metas };
+                    let color = solver.items[item].get_sockets()[socket].get_color();
+                    let gem_of = |row: usize| -> &Gem { return if row < res.metas { &solver.gems[row] } else { &solver.metas[row - res.metas] }; };
+                    res.options.push(pool.clone().collect());
+                    res.matching.push(if chasing { Some(pool.clone().filter(|&row| gem_of(row).get_colors().contains(&color)).collect()) } else { None });
+                    most(&mut stats, &mut pool.flat_map(|row| gem_of(row).get_bonuses().iter()));
+                    let bonus = solver.items[item].get_socket_bonus().as_ref().filter(|_| settles);
+                    res.bonus.push(bonus.map(|bonus| add_row(&mut res.rows, &mut std::iter::once(bonus))));
+                    most(&mut stats, &mut bonus.into_iter());
+                    if settles { end = depth + 1; }
+                },
+            }
+            res.ends.push(if res.matching.last().unwrap().is_some() { end } else { depth + 1 });
+            reach[depth] = stats;
+        }
+        res.options.reverse();
+        res.matching.reverse();
+        res.bonus.reverse();
+        res.ends.reverse();
+        // Meta gems already in place may or may not end up active.
+        for gem in solver.items.iter().flat_map(|item| item.get_sockets().iter()).filter_map(|socket| socket.get_gem().as_ref()).filter(|gem| gem.is_meta()) {
+            res.placed.push(add_row(&mut res.rows, &mut gem.get_bonuses().iter()));
+            for stats in reach.iter_mut() {
+                most(stats, &mut gem.get_bonuses().iter());
+            }
+        }
+        res.limited.resize(res.rows.len(), None);
+        res.reach = reach;
+        let fixed = |ind: usize| -> Option<f64> {
+            if matches!(reqs[ind], Requirement::RequirementWeighted(_)) { return Some(1.0); }
+            if res.rows.iter().any(|row| row[ind].is_infinite()) || res.rows.iter().all(|row| row[ind] == 0.0) { return Some(0.0); }
+            return None;
+        };
+        let mut shares: Vec<f64> = (0..reqs.len()).map(|ind| fixed(ind).unwrap_or(0.0)).collect();
+        // The bound at the start is convex in every share, so each is tuned in turn by ternary search.
+        let tuned: Vec<usize> = (0..reqs.len()).filter(|&ind| fixed(ind).is_none()).collect();
+        let (_, deltas) = res.deltas(reqs, 0, cur_stats, &mut std::iter::empty());
+        let start = |shares: &[f64]| -> f64 {
+            let table = res.table(shares);
+            return res.total(&table, 0, true, &solver.room, None, res.rest(&table, &deltas, &[]));
+        };
+        for _ in 0..3 {
+            for &ind in &tuned {
+                let (mut low, mut high) = (0.0, 1.0);
+                for _ in 0..30 {
+                    let (left, right) = ((2.0 * low + high) / 3.0, (low + 2.0 * high) / 3.0);
+                    shares[ind] = left;
+                    let at_left = start(&shares);
+                    shares[ind] = right;
+                    if at_left < start(&shares) { high = right; } else { low = left; }
+                }
+                shares[ind] = (low + high) / 2.0;
+            }
+        }
+        res.tables.push(res.table(&shares));
+        if !tuned.is_empty() { res.tables.push(res.table(&(0..reqs.len()).map(|ind| fixed(ind).unwrap_or(0.0)).collect::<Vec<f64>>())); }
+        return res;
+    }
+    fn value(row: &[f64], shares: &[f64]) -> f64 {
+        return row.iter().zip(shares).filter(|(_, share)| **share > 0.0).map(|(rate, share)| rate * share).sum();
+    }
+    fn table(&self, shares: &[f64]) -> Table {
+        let values: Vec<f64> = self.rows.iter().map(|row| Self::value(row, shares)).collect();
+        let best = |rows: &[usize], limited: bool| -> f64 {
+            return rows.iter().filter(|&&row| limited || self.limited[row].is_none()).map(|&row| values[row]).fold(f64::NEG_INFINITY, f64::max);
+        };
+        let len = self.options.len();
+        let (mut without, mut with, mut after) = (vec![0.0; len + 1], vec![f64::NEG_INFINITY; len + 1], vec![0.0; len + 1]);
+        let mut extra = vec![vec![0.0_f64; len + 1]; self.limits];
+        for depth in (0..len).rev() {
+            let goes_on = self.ends[depth] > depth + 1;
+            // A socket no gem fits into stays empty.
+            let any = best(&self.options[depth], false);
+            without[depth] = any.max(0.0) + if goes_on { without[depth + 1] } else { 0.0 };
+            let mut matched = f64::NEG_INFINITY;
+            if let Some(ref matching) = self.matching[depth] {
+                // Matching the socket may take a gem with a limit, which then also counts as extra.
+                let unlimited = best(matching, false);
+                matched = if unlimited == f64::NEG_INFINITY { best(matching, true) } else { unlimited };
+                with[depth] = matched + self.bonus[depth].map_or(0.0, |row| values[row]) + if goes_on { with[depth + 1] } else { 0.0 };
+            }
+            after[depth] = if goes_on { after[depth + 1] } else { without[depth + 1].max(with[depth + 1]) + after[depth + 1] };
+            for (limit, more) in extra.iter_mut().enumerate() {
+                more[depth] = more[depth + 1];
+                for &row in self.options[depth].iter().filter(|&&row| self.limited[row] == Some(limit)) {
+                    let matches = self.matching[depth].as_ref().is_some_and(|matching| matching.contains(&row));
+                    let floor = if matches { any.min(matched) } else { any }.max(0.0);
+                    more[depth] = more[depth].max(values[row] - floor);
+                }
+            }
+        }
+        return Table{shares: shares.to_vec(), values, without, with, after, extra};
+    }
+    // Gain of stats and what adding the most of every stat from depth on to them gains for each requirement, minus infinity for
+    // a minimum it can't reach.
+    fn deltas(&self, reqs: &[Requirement], depth: usize, stats: &CurStats, metas: &mut dyn Iterator<Item = &Bonus>) -> (f64, Vec<f64>) {
+        let mut reach = stats.sum_of(&self.reach[depth]);
+        for bonus in metas {
+            bonus.apply_bonus(&mut reach);
+        }
+        let mut gain = 0.0;
+        let deltas = reqs.iter().map(|req| {
+            let from = req.gain(stats);
+            gain += from;
+            return if req.shortfall(&reach) > EPSILON { f64::NEG_INFINITY } else { req.gain(&reach) - from };
+        }).collect();
+        return (gain, deltas);
+    }
+    // What the table puts on meta gems and on the requirements beyond their shares.
+    fn rest(&self, table: &Table, deltas: &[f64], metas: &[usize]) -> f64 {
+        let placed: f64 = self.placed.iter().copied().chain(metas.iter().map(|ind| self.metas + ind)).map(|row| table.values[row].max(0.0)).sum();
+        return placed + deltas.iter().zip(&table.shares).map(|(delta, share)| (1.0 - share) * delta).sum::<f64>();
+    }
+    // Gain of stats and the rest of every table, None once a minimum is out of reach.
+    fn rests(&self, reqs: &[Requirement], depth: usize, stats: &CurStats, metas: &[usize]) -> (f64, Option<Vec<f64>>) {
+        let (gain, deltas) = self.deltas(reqs, depth, stats, &mut metas.iter().flat_map(|&ind| self.meta_bonuses[ind].iter()));
+        if deltas.contains(&f64::NEG_INFINITY) { return (gain, None); }
+        return (gain, Some(self.tables.iter().map(|table| self.rest(table, &deltas, metas)).collect()));
+    }
+    // One less room for the limit used.
+    fn total(&self, table: &Table, depth: usize, can_match: bool, room: &[u32], used: Option<usize>, rest: f64) -> f64 {
+        let item = if can_match { table.without[depth].max(table.with[depth]) } else { table.without[depth] };
+        let extra: f64 = table.extra.iter().zip(room).enumerate().map(|(limit, (more, &room))| more[depth] * f64::from(room - u32::from(used == Some(limit)))).sum();
+        return item + table.after[depth] + extra + rest;
+    }
+    // The most the decisions from depth on add to the gain of the stats rests were made for.
+    fn at(&self, depth: usize, can_match: bool, room: &[u32], used: Option<usize>, rests: &[f64]) -> f64 {
+        return self.tables.iter().zip(rests).map(|(table, &rest)| self.total(table, depth, can_match, room, used, rest)).fold(f64::INFINITY, f64::min);
+    }
+    // The most taking an option at depth, with the socket bonus if it matched the item, adds with the decisions after it. Never
+    // below Bound::at right after it, which only has less of the same left for the requirements.
+    fn estimate(&self, depth: usize, option: Option<usize>, bonus: bool, can_match: bool, room: &[u32], rests: &[f64]) -> f64 {
+        let row = option.map(|ind| self.options[depth][ind]);
+        let used = row.and_then(|row| self.limited[row]);
+        return self.tables.iter().zip(rests).map(|(table, &rest)| {
+            let own = row.map_or(0.0, |row| table.values[row]) + if bonus { self.bonus[depth].map_or(0.0, |row| table.values[row]) } else { 0.0 };
+            return own + self.total(table, depth + 1, can_match, room, used, rest);
+        }).fold(f64::INFINITY, f64::min);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use crate::catalog::Catalog;
+    use crate::items::GemSocket;
+    use crate::simulator::requirements::{RequirementCap, RequirementWeighted};
+    use crate::{ItemSlot, Stat};
+
+    // Every way to fill the sockets from the one at ind on, with gems of the build counted when they're active.
+    fn brute_force(items: &mut Vec<Item>, sockets: &[(usize, usize)], ind: usize, gems: &[Gem], reqs: &[Requirement]) -> f64 {
+        if ind == sockets.len() {
+            let mut stats = CurStats::new();
+            for item in items.iter() {
+                if let Some(enchant) = item.get_enchantment() { enchant.get_bonuses().apply_bonuses(&mut stats); }
+                for gem in item.get_sockets().iter().filter_map(|socket| socket.get_gem().as_ref()).filter(|gem| !gem.is_meta()) {
+                    gem.get_bonuses().apply_bonuses(&mut stats);
+                }
+                if item.is_socket_bonus_active() { item.apply_socket_bonus(&mut stats); }
+            }
+            return Distribution::new(stats, reqs, items).get_gain();
+        }
+        let (item, socket) = sockets[ind];
+        let color = items[item].get_sockets()[socket].get_color();
+        let mut best = f64::NEG_INFINITY;
+        for gem in gems.iter().filter(|gem| gem.fits(color)) {
+            if !gem.is_within_limit(items) { continue; }
+            items[item].get_socket_mut(socket).set_gem(gem);
+            best = best.max(brute_force(items, sockets, ind + 1, gems, reqs));
+            items[item].get_socket_mut(socket).set_empty();
+        }
+        return best;
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let catalog = Catalog::from_str(concat!(
+            "[[gem]]\nname = \"red\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"Agility\", val = 16 }]\n\n",
+            "[[gem]]\nname = \"yellow\"\ncolors = [\"Yellow\"]\nbonuses = [{ stat = \"HitRate\", val = 16 }]\n\n",
+            "[[gem]]\nname = \"orange\"\ncolors = [\"Red\", \"Yellow\"]\nbonuses = [{ stat = \"Agility\", val = 8 }, { stat = \"HitRate\", val = 8 }]\n\n",
+            "[[gem]]\nname = \"blue\"\ncolors = [\"Blue\"]\nbonuses = [{ stat = \"Stamina\", val = 24 }]\n\n",
+            "[[gem]]\nname = \"eye\"\ncolors = [\"Red\"]\nbonuses = [{ stat = \"Agility\", val = 27 }]\nlimit = { kind = \"UniqueEquipped\" }\n\n",
+            "[[gem]]\nname = \"meta\"\ncolors = [\"Meta\"]\nbonuses = [{ stat = \"Agility\", val = 30 }]\nactivation = [{ rule = \"AtLeast\", color = \"Blue\", count = 2 }]\n\n",
+            "[[gem]]\nname = \"plain meta\"\ncolors = [\"Meta\"]\nbonuses = [{ stat = \"Agility\", val = 12 }]\n\n",
+            "[[enchant]]\nname = \"hit\"\nslots = [\"Legs\"]\nbonuses = [{ stat = \"HitRate\", val = 20 }]\n\n",
+            "[[enchant]]\nname = \"agi\"\nslots = [\"Legs\"]\nbonuses = [{ stat = \"Agility\", val = 14 }]\n",
+        )).unwrap();
+        let optimizer = BestBonusFinder::from_catalog(&catalog);
+        let reqs = vec![
+            Requirement::RequirementCap(RequirementCap::new(Stat::HitRate, 40, 2.0)),
+            Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Agility, 1.0)),
+            Requirement::RequirementWeighted(RequirementWeighted::new(Stat::Stamina, 0.3)),
+        ];
+        let socketed = |name: &str, slot: ItemSlot, colors: &[Color], bonus: Option<Bonus>| -> Item {
+            return Item::new(String::from(name), slot, Bonuses::new(vec![]), colors.iter().map(|&color| GemSocket::new(color)).collect(), bonus, None);
+        };
+        let items = vec![
+            socketed("head", ItemSlot::Head, &[Color::Meta, Color::Blue], Some(Bonus::new(Stat::HitRate, 8))),
+            socketed("chest", ItemSlot::Chest, &[Color::Red, Color::Yellow, Color::Yellow], Some(Bonus::new(Stat::Agility, 12))),
+            socketed("bracer", ItemSlot::Bracer, &[Color::Prismatic], None),
+            socketed("legs", ItemSlot::Legs, &[Color::Blue, Color::Red], Some(Bonus::new(Stat::Agility, 6))),
+        ];
+        let sockets: Vec<(usize, usize)> = items.iter().enumerate().flat_map(|(item, it)| (0..it.get_sockets().len()).map(move |socket| (item, socket))).collect();
+        let mut expected = f64::NEG_INFINITY;
+        for enchant in optimizer.get_enchants_for_slot(ItemSlot::Legs) {
+            let mut items = items.clone();
+            items[3].set_enchantment(enchant);
+            expected = expected.max(brute_force(&mut items, &sockets, 0, optimizer.get_gems(), &reqs));
+        }
+        for prechant in [true, false] {
+            let solved = Solver::new(items.clone(), &reqs, &optimizer, true, true, prechant).solve(CurStats::new(), None).unwrap();
+            assert!((expected - solved.get_gain()).abs() < EPSILON);
+        }
+    }
+}